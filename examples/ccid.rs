@@ -0,0 +1,36 @@
+extern crate yubico_manager;
+
+use yubico_manager::ccid::CcidTransport;
+use yubico_manager::config::Config;
+use yubico_manager::Yubico;
+
+fn main() {
+    // The name of an NFC reader can be given to reach a key over NFC
+    let reader = std::env::args().nth(1);
+
+    let transport = match CcidTransport::connect(reader.as_deref()) {
+        Ok(transport) => transport,
+        Err(err) => {
+            println!("Yubikey not found: {}", err);
+            return;
+        }
+    };
+    let mut yubi = Yubico::with_transport(transport);
+
+    if let Ok(device) = yubi.find_yubikey() {
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id);
+
+        match yubi.read_status(config.clone()) {
+            Ok(status) => println!("Firmware version {:?}", status.version),
+            Err(err) => println!("{:?}", err),
+        }
+        match yubi.read_serial_number(config) {
+            Ok(serial) => println!("Serial Number {}", serial),
+            Err(err) => println!("{:?}", err),
+        }
+    } else {
+        println!("Yubikey not found");
+    }
+}
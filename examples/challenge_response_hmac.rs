@@ -2,7 +2,8 @@ extern crate hex;
 extern crate yubico_manager;
 
 use std::ops::Deref;
-use yubico_manager::config::{Config, Mode, Slot};
+use std::time::Duration;
+use yubico_manager::config::{Config, Mode, Slot, TouchEvent};
 use yubico_manager::Yubico;
 
 fn main() {
@@ -19,7 +20,14 @@ fn main() {
             .set_product_id(device.product_id)
             .set_variable_size(true)
             .set_mode(Mode::Sha1)
-            .set_slot(Slot::Slot2);
+            .set_slot(Slot::Slot2)
+            // Give up if the slot requires a touch and nobody touches the key
+            .set_timeout(Duration::from_secs(15))
+            .set_touch_callback(|event| {
+                if event == TouchEvent::Waiting {
+                    println!("Touch your YubiKey now");
+                }
+            });
 
         // Challenge can not be greater than 64 bytes
         let challenge = String::from("mychallenge");
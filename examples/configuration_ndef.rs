@@ -0,0 +1,31 @@
+extern crate yubico_manager;
+
+use yubico_manager::config::{Config, Slot};
+use yubico_manager::ndef::NdefPayload;
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::new();
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id);
+
+        // The "https://" prefix is sent as a single byte
+        let payload = NdefPayload::Uri("https://my.yubico.com/neo/".to_string());
+
+        if let Err(err) = yubi.write_ndef(config, Slot::Slot1, &payload, None) {
+            println!("{:?}", err);
+        } else {
+            println!("NDEF record written");
+        }
+    } else {
+        println!("Yubikey not found");
+    }
+}
@@ -0,0 +1,46 @@
+extern crate rand;
+extern crate yubico_manager;
+
+use rand::thread_rng;
+use yubico_manager::config::{Command, Config};
+use yubico_manager::configure::{DeviceModeConfig, OathTokenId};
+use yubico_manager::hmacmode::HmacKey;
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::new();
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id)
+            .set_command(Command::Configuration2);
+
+        let serial_number = yubi.read_serial_number(config.clone()).unwrap_or(0);
+
+        // Secret must have 20 bytes, share it with the validation server
+        let hmac_key: HmacKey = HmacKey::generate(thread_rng());
+
+        // Codes will be typed as "ubhe" + serial number + 6 digits
+        let token_id = OathTokenId::yubico(serial_number);
+
+        let mut device_config = DeviceModeConfig::default();
+        if let Err(err) = device_config.oath_hotp(&hmac_key, 6, Some(&token_id), 0) {
+            println!("{}", err);
+            return;
+        }
+
+        if let Err(err) = yubi.write_config(config, &mut device_config) {
+            println!("{:?}", err);
+        } else {
+            println!("Device configured");
+        }
+    } else {
+        println!("Yubikey not found");
+    }
+}
@@ -0,0 +1,39 @@
+extern crate yubico_manager;
+
+use yubico_manager::config::{Command, Config};
+use yubico_manager::configure::DeviceModeConfig;
+use yubico_manager::keyboard::KeyboardLayout;
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::new();
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id)
+            .set_command(Command::Configuration2);
+
+        // Password can not be longer than 38 characters
+        let password = "My-Static-Password!";
+
+        let mut device_config = DeviceModeConfig::default();
+        if let Err(err) = device_config.static_password(password, KeyboardLayout::Us) {
+            println!("{}", err);
+            return;
+        }
+
+        if let Err(err) = yubi.write_config(config, &mut device_config) {
+            println!("{:?}", err);
+        } else {
+            println!("Device configured");
+        }
+    } else {
+        println!("Yubikey not found");
+    }
+}
@@ -0,0 +1,51 @@
+extern crate rand;
+extern crate yubico_manager;
+
+use rand::thread_rng;
+use yubico_manager::config::{Command, Config};
+use yubico_manager::configure::{DeviceModeConfig, TicketFlags};
+use yubico_manager::otpmode::Aes128Key;
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::new();
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id)
+            .set_command(Command::Configuration1);
+
+        // Public Identity is sent in clear before each OTP (up to 16 bytes)
+        let public_identity: &[u8] = b"\x01\x02\x03\x04\x05\x06";
+
+        // Private Identity must have 6 bytes (encrypted inside each OTP)
+        let private_identity: &[u8; 6] = b"norway";
+
+        let aes128_key: Aes128Key = Aes128Key::generate(thread_rng());
+
+        let mut device_config = DeviceModeConfig::default();
+        if let Err(err) = device_config.yubico_otp(
+            public_identity,
+            private_identity,
+            &aes128_key,
+            TicketFlags::APPEND_CR,
+        ) {
+            println!("{}", err);
+            return;
+        }
+
+        if let Err(err) = yubi.write_config(config, &mut device_config) {
+            println!("{:?}", err);
+        } else {
+            println!("Device configured");
+        }
+    } else {
+        println!("Yubikey not found");
+    }
+}
@@ -0,0 +1,28 @@
+extern crate yubico_manager;
+
+use yubico_manager::config::{Config, Slot};
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::new();
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id);
+
+        // Pass the access code if the slot is protected
+        if let Err(err) = yubi.delete_slot(config, Slot::Slot2, None) {
+            println!("{:?}", err);
+        } else {
+            println!("Slot deleted");
+        }
+    } else {
+        println!("Yubikey not found");
+    }
+}
@@ -0,0 +1,43 @@
+extern crate yubico_manager;
+
+use yubico_manager::config::Config;
+use yubico_manager::deviceinfo::{Capabilities, DeviceConfigUpdate};
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::new();
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id);
+
+        // Requires a YubiKey 5
+        let info = match yubi.read_device_info(config.clone()) {
+            Ok(info) => info,
+            Err(error) => {
+                println!("{}", error);
+                return;
+            }
+        };
+
+        // Disable OpenPGP and PIV over USB, and apply the change at once
+        let update = DeviceConfigUpdate::default()
+            .set_usb_enabled(info.usb_enabled - Capabilities::OPENPGP - Capabilities::PIV)
+            .set_reboot(true);
+
+        if let Err(err) = yubi.write_device_config(config, &update) {
+            println!("{:?}", err);
+        } else {
+            // The key reboots before it can confirm the write
+            println!("Device configuration sent");
+        }
+    } else {
+        println!("Yubikey not found");
+    }
+}
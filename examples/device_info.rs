@@ -0,0 +1,35 @@
+extern crate yubico_manager;
+
+use yubico_manager::config::Config;
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::new();
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id);
+
+        // Requires a YubiKey 4.1 or later
+        match yubi.read_device_info(config) {
+            Ok(info) => {
+                println!("Serial Number {:?}", info.serial);
+                println!("Form Factor {:?}", info.form_factor);
+                println!("USB Enabled {:?}", info.usb_enabled);
+                println!("NFC Enabled {:?}", info.nfc_enabled);
+                println!("Locked {}", info.is_locked);
+            }
+            Err(error) => {
+                println!("{}", error);
+            }
+        };
+    } else {
+        println!("Yubikey not found");
+    }
+}
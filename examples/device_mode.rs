@@ -0,0 +1,28 @@
+extern crate yubico_manager;
+
+use yubico_manager::config::{Config, DeviceMode};
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::new();
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id);
+
+        // Disable the CCID interface, wait 15 seconds for touch in challenge-response
+        if let Err(err) = yubi.set_device_mode(config, DeviceMode::OtpU2f, 15, 0) {
+            println!("{:?}", err);
+        } else {
+            println!("Device mode set, re-insert the key to apply it");
+        }
+    } else {
+        println!("Yubikey not found");
+    }
+}
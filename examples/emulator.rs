@@ -0,0 +1,73 @@
+extern crate hex;
+extern crate yubico_manager;
+
+use yubico_manager::config::{Command, Config, Mode, Slot};
+use yubico_manager::configure::DeviceModeConfig;
+use yubico_manager::emulator::Emulator;
+use yubico_manager::hmacmode::HmacKey;
+use yubico_manager::otpmode::Aes128Key;
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::with_transport(Emulator::new(1234567));
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id);
+
+        let serial_number = yubi.read_serial_number(config.clone()).unwrap();
+        println!("Serial Number {}", serial_number);
+
+        let status = yubi.read_status(config.clone()).unwrap();
+        println!("Firmware version {:?}", status.version);
+
+        // Slot 1: HMAC-SHA1 challenge-response
+        let hmac_key = HmacKey::from_slice(b"my_awesome_secret_20");
+        let mut device_config = DeviceModeConfig::default();
+        device_config.challenge_response_hmac(&hmac_key, true, false);
+        yubi.write_config(
+            config.clone().set_command(Command::Configuration1),
+            &mut device_config,
+        )
+        .unwrap();
+
+        let challenge = b"mychallenge";
+        let hmac_result = yubi
+            .challenge_response_hmac(challenge, config.clone().set_slot(Slot::Slot1))
+            .unwrap();
+        println!(
+            "HMAC {} valid: {}",
+            hex::encode(&*hmac_result),
+            hmac_result.check(&hmac_key, challenge)
+        );
+
+        // Slot 2: Yubico OTP challenge-response
+        let private_identity: &[u8; 6] = b"norway";
+        let aes128_key = Aes128Key::from_slice(b"_awesome_secret_");
+        let mut device_config = DeviceModeConfig::default();
+        device_config.challenge_response_otp(&aes128_key, private_identity, false);
+        yubi.write_config(
+            config.clone().set_command(Command::Configuration2),
+            &mut device_config,
+        )
+        .unwrap();
+
+        let challenge = b"my_challenge";
+        let otp_result = yubi
+            .challenge_response_otp(challenge, config.set_mode(Mode::Otp).set_slot(Slot::Slot2))
+            .unwrap();
+        let otp = otp_result.check(&aes128_key, challenge).unwrap();
+        println!(
+            "OTP private identity valid: {}",
+            &otp.uid == private_identity
+        );
+    } else {
+        println!("Yubikey not found");
+    }
+}
@@ -0,0 +1,28 @@
+extern crate yubico_manager;
+
+use yubico_manager::config::Config;
+use yubico_manager::hidraw::HidrawTransport;
+use yubico_manager::Yubico;
+
+fn main() {
+    // No kernel driver is detached: the key keeps typing OTPs
+    let mut yubi = Yubico::with_transport(HidrawTransport::new());
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id);
+
+        match yubi.read_serial_number(config) {
+            Ok(serial) => println!("Serial Number {}", serial),
+            Err(err) => println!("{:?}", err),
+        }
+    } else {
+        println!("Yubikey not found");
+    }
+}
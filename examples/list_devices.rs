@@ -0,0 +1,33 @@
+extern crate yubico_manager;
+
+use yubico_manager::config::Config;
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::new();
+
+    match yubi.list_devices() {
+        Ok(devices) => {
+            for device in devices {
+                println!(
+                    "Bus {:03} Address {:03} Product ID {:?} Serial {:?} Version {:?}",
+                    device.bus_number,
+                    device.address,
+                    device.product_id,
+                    device.serial,
+                    device.version
+                );
+
+                // Each key can then be addressed individually
+                let config =
+                    Config::default_config().set_bus_address(device.bus_number, device.address);
+                if let Ok(status) = yubi.read_status(config) {
+                    println!("Programming sequence {}", status.pgm_seq);
+                }
+            }
+        }
+        Err(error) => {
+            println!("{}", error);
+        }
+    }
+}
@@ -0,0 +1,31 @@
+extern crate yubico_manager;
+
+use yubico_manager::config::Config;
+use yubico_manager::keyboard::{KeyboardLayout, ScanMap};
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::new();
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id);
+
+        // Type OTPs correctly on AZERTY keyboards
+        let scan_map = ScanMap::from_layout(KeyboardLayout::Fr).unwrap();
+
+        if let Err(err) = yubi.write_scan_map(config, &scan_map) {
+            println!("{:?}", err);
+        } else {
+            println!("Scan map written");
+        }
+    } else {
+        println!("Yubikey not found");
+    }
+}
@@ -0,0 +1,34 @@
+extern crate hex;
+extern crate yubico_manager;
+
+use yubico_manager::config::{Config, Mode, Slot};
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::new();
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_variable_size(true)
+            .set_mode(Mode::Sha1)
+            .set_slot(Slot::Slot2);
+
+        // The device stays open until the session is dropped
+        let mut session = yubi.open(&device).unwrap();
+        for i in 0..10 {
+            let challenge = format!("mychallenge{}", i);
+            let hmac_result = session
+                .challenge_response_hmac(challenge.as_bytes(), config.clone())
+                .unwrap();
+
+            println!("{}: {}", challenge, hex::encode(&hmac_result.0));
+        }
+    } else {
+        println!("Yubikey not found");
+    }
+}
@@ -0,0 +1,32 @@
+extern crate yubico_manager;
+
+use yubico_manager::config::{Config, Slot};
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::new();
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id);
+
+        for slot in [Slot::Slot1, Slot::Slot2] {
+            match yubi.slot_status(config.clone(), slot.clone()) {
+                Ok(status) if !status.configured => println!("{:?} is empty", slot),
+                Ok(status) => println!(
+                    "{:?} is configured, touch required: {}",
+                    slot, status.touch_required
+                ),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+    } else {
+        println!("Yubikey not found");
+    }
+}
@@ -0,0 +1,35 @@
+extern crate yubico_manager;
+
+use yubico_manager::config::{Config, Slot};
+use yubico_manager::configure::{ExtendedFlags, TicketFlags, UpdateConfig};
+use yubico_manager::Yubico;
+
+fn main() {
+    let mut yubi = Yubico::new();
+
+    if let Ok(device) = yubi.find_yubikey() {
+        println!(
+            "Vendor ID: {:?} Product ID {:?}",
+            device.vendor_id, device.product_id
+        );
+
+        let config = Config::default_config()
+            .set_vendor_id(device.vendor_id)
+            .set_product_id(device.product_id);
+
+        // The slot must have been configured with ExtendedFlags::ALLOW_UPDATE.
+        // All updatable flags are replaced: keep ALLOW_UPDATE set to allow
+        // further updates, and the serial visible.
+        let update = UpdateConfig::default()
+            .set_ticket_flags(TicketFlags::APPEND_CR)
+            .set_extended_flags(ExtendedFlags::ALLOW_UPDATE | ExtendedFlags::SERIAL_API_VISIBLE);
+
+        if let Err(err) = yubi.update_slot(config, Slot::Slot1, &update, None) {
+            println!("{:?}", err);
+        } else {
+            println!("Slot updated");
+        }
+    } else {
+        println!("Yubikey not found");
+    }
+}
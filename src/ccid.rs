@@ -0,0 +1,389 @@
+//! The OTP application over CCID, as a smart card applet.
+//!
+//! This is the only way to reach the OTP application through an NFC
+//! reader. The applet takes the same commands as the HID interface,
+//! one APDU each, so `CcidTransport` rebuilds the frames the crate
+//! writes as feature reports, sends them to the applet, and serves the
+//! answers back as feature reports: the rest of the crate does not
+//! know the difference.
+//!
+//! The card itself is reached through the `SmartCard` trait. With the
+//! `pcsc` feature, it is implemented for the card handles of the `pcsc`
+//! crate, and `CcidTransport::connect` opens one. The applet blocks
+//! until the key is touched, so a challenge waiting for touch can not
+//! be cancelled.
+
+use crate::config::Command;
+use crate::configure::SIZEOF_CONFIG;
+use crate::keyboard::SCAN_MAP_SIZE;
+use crate::manager::Flags;
+use crate::ndef::SIZEOF_NDEF;
+use crate::sec::crc16;
+use crate::transport::Transport;
+use crate::yubicoerror::YubicoError;
+use crate::{Device, VENDOR_ID};
+
+const FRAME_SIZE: usize = 70;
+const PAYLOAD_SIZE: usize = 64;
+const RESET_SEQ: u8 = 0x0f;
+const ACC_CODE_SIZE: usize = 6;
+const STATUS_SIZE: usize = 6;
+
+const AID_OTP: [u8; 7] = [0xa0, 0x00, 0x00, 0x05, 0x27, 0x20, 0x01];
+const INS_SELECT: u8 = 0xa4;
+const INS_CONFIG: u8 = 0x01;
+const SW_OK: u16 = 0x9000;
+
+/// A connection to a smart card, such as a PC/SC card handle.
+pub trait SmartCard {
+    /// Sends a command APDU, returning the response APDU along with
+    /// its status word.
+    fn transmit(&mut self, apdu: &[u8]) -> Result<Vec<u8>, YubicoError>;
+}
+
+/// A transport to the OTP applet of a YubiKey reached as a smart card.
+pub struct CcidTransport<C: SmartCard> {
+    card: C,
+    opened: bool,
+    /// Version, programming sequence and touch level, as in the
+    /// feature reports.
+    status: [u8; STATUS_SIZE],
+    frame: [u8; FRAME_SIZE],
+    response: Vec<u8>,
+    response_seq: Option<usize>,
+}
+
+impl<C: SmartCard> CcidTransport<C> {
+    pub fn new(card: C) -> Self {
+        CcidTransport {
+            card,
+            opened: false,
+            status: [0; STATUS_SIZE],
+            frame: [0; FRAME_SIZE],
+            response: Vec::new(),
+            response_seq: None,
+        }
+    }
+
+    /// Returns the underlying card.
+    pub fn card(&mut self) -> &mut C {
+        &mut self.card
+    }
+
+    /// Sends an APDU, returning the response data if the status word
+    /// reports success.
+    fn send_apdu(&mut self, ins: u8, p1: u8, data: &[u8]) -> Result<Vec<u8>, YubicoError> {
+        let mut apdu = vec![0, ins, p1, 0];
+        if !data.is_empty() {
+            apdu.push(data.len() as u8);
+            apdu.extend_from_slice(data);
+        }
+
+        let mut response = self.card.transmit(&apdu)?;
+        if response.len() < 2 {
+            return Err(YubicoError::InvalidResponse);
+        }
+        let sw = response.split_off(response.len() - 2);
+        match u16::from_be_bytes([sw[0], sw[1]]) {
+            SW_OK => Ok(response),
+            sw => Err(YubicoError::SmartCardError(sw)),
+        }
+    }
+
+    fn set_status(&mut self, status: &[u8]) -> Result<(), YubicoError> {
+        // Older applets only return the firmware version, without the
+        // programming sequence writes are checked against.
+        if status.len() < STATUS_SIZE {
+            return Err(YubicoError::InvalidResponse);
+        }
+        self.status.copy_from_slice(&status[..STATUS_SIZE]);
+        Ok(())
+    }
+
+    /// Sends the command of a complete frame to the applet.
+    fn process_frame(&mut self) -> Result<(), YubicoError> {
+        let payload = &self.frame[..PAYLOAD_SIZE];
+        let command = self.frame[PAYLOAD_SIZE];
+        let (len, answers_status) = command_layout(command, payload)?;
+        let data = payload[..len].to_vec();
+
+        // The HID interface signals a rejected write by leaving the
+        // programming sequence alone, and a challenge it can not answer
+        // (empty slot, no touch) by never answering; the applet reports
+        // both with an error status word.
+        let response = match self.send_apdu(INS_CONFIG, command, &data) {
+            Err(YubicoError::SmartCardError(_)) if answers_status => {
+                return Err(YubicoError::ConfigNotWritten)
+            }
+            Err(YubicoError::SmartCardError(_)) => return Err(YubicoError::Timeout),
+            response => response?,
+        };
+        if answers_status {
+            self.set_status(&response)
+        } else {
+            // The applet leaves out the CRC the HID interface adds.
+            self.response = response;
+            let crc = !crc16(&self.response);
+            self.response.extend_from_slice(&crc.to_le_bytes());
+            let padding = (7 - self.response.len() % 7) % 7;
+            self.response.resize(self.response.len() + padding, 0);
+            self.response_seq = Some(0);
+            Ok(())
+        }
+    }
+}
+
+/// Returns the length of the payload the applet expects for `command`,
+/// and whether it answers with the status of the key rather than with
+/// data.
+fn command_layout(command: u8, payload: &[u8]) -> Result<(usize, bool), YubicoError> {
+    let config = SIZEOF_CONFIG + ACC_CODE_SIZE;
+    let layout = match command {
+        c if c == Command::Configuration1 as u8
+            || c == Command::Configuration2 as u8
+            || c == Command::Update1 as u8
+            || c == Command::Update2 as u8
+            || c == Command::Swap as u8 =>
+        {
+            (config, true)
+        }
+        c if c == Command::Ndef1 as u8 || c == Command::Ndef2 as u8 => {
+            (SIZEOF_NDEF + ACC_CODE_SIZE, true)
+        }
+        c if c == Command::DeviceConfig as u8 => (4, true),
+        c if c == Command::ScanMap as u8 => (SCAN_MAP_SIZE, true),
+        c if c == Command::WriteDeviceConfig as u8 => (1 + payload[0] as usize, true),
+        c if c == Command::DeviceSerial as u8 => (0, false),
+        c if c == Command::DeviceInfo as u8 => (1, false),
+        c if c == Command::ChallengeHmac1 as u8 || c == Command::ChallengeHmac2 as u8 => {
+            (PAYLOAD_SIZE, false)
+        }
+        c if c == Command::ChallengeOtp1 as u8 || c == Command::ChallengeOtp2 as u8 => (6, false),
+        _ => return Err(YubicoError::CommandNotSupported),
+    };
+    if layout.0 > PAYLOAD_SIZE {
+        return Err(YubicoError::CanNotWriteToDevice);
+    }
+    Ok(layout)
+}
+
+#[cfg(feature = "pcsc")]
+impl SmartCard for pcsc::Card {
+    fn transmit(&mut self, apdu: &[u8]) -> Result<Vec<u8>, YubicoError> {
+        let mut buf = [0; pcsc::MAX_BUFFER_SIZE];
+        let response = pcsc::Card::transmit(self, apdu, &mut buf)?;
+        Ok(response.to_vec())
+    }
+}
+
+#[cfg(feature = "pcsc")]
+impl CcidTransport<pcsc::Card> {
+    /// Connects to the first PC/SC reader whose name contains `reader`,
+    /// or to the first YubiKey plugged over USB if `reader` is `None`.
+    /// NFC readers have names of their own, and have to be given.
+    pub fn connect(reader: Option<&str>) -> Result<Self, YubicoError> {
+        let context = pcsc::Context::establish(pcsc::Scope::User)?;
+        let pattern = reader.unwrap_or("Yubico");
+        let name = context
+            .list_readers_owned()?
+            .into_iter()
+            .find(|name| name.to_string_lossy().contains(pattern))
+            .ok_or(YubicoError::DeviceNotFound)?;
+        let card = context.connect(&name, pcsc::ShareMode::Shared, pcsc::Protocols::ANY)?;
+        Ok(CcidTransport::new(card))
+    }
+}
+
+impl<C: SmartCard> Transport for CcidTransport<C> {
+    /// The card is the only device.
+    fn devices(&mut self) -> Result<Vec<Device>, YubicoError> {
+        Ok(vec![Device {
+            product_id: 0,
+            vendor_id: VENDOR_ID,
+            bus_number: 0,
+            address: 0,
+            serial: None,
+            version: None,
+        }])
+    }
+
+    /// Selects the OTP applet.
+    fn open(&mut self, _device: &Device) -> Result<(), YubicoError> {
+        let status = self.send_apdu(INS_SELECT, 0x04, &AID_OTP)?;
+        self.set_status(&status)?;
+        self.response.clear();
+        self.response_seq = None;
+        self.opened = true;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), YubicoError> {
+        self.opened = false;
+        Ok(())
+    }
+
+    fn get_feature_report(&mut self, buf: &mut [u8; 8]) -> Result<usize, YubicoError> {
+        if !self.opened {
+            return Err(YubicoError::OpenDeviceError);
+        }
+
+        match self.response_seq {
+            Some(seq) => {
+                let chunks = self.response.len() / 7;
+                let seq = seq % chunks;
+                buf[..7].copy_from_slice(&self.response[seq * 7..seq * 7 + 7]);
+                buf[7] = Flags::RESP_PENDING_FLAG.bits() | seq as u8;
+                self.response_seq = Some(seq + 1);
+            }
+            None => {
+                buf[0] = 0;
+                buf[1..7].copy_from_slice(&self.status);
+                buf[7] = 0;
+            }
+        }
+        Ok(8)
+    }
+
+    fn set_feature_report(&mut self, packet: &[u8; 8]) -> Result<(), YubicoError> {
+        if !self.opened {
+            return Err(YubicoError::OpenDeviceError);
+        }
+
+        let flags = packet[7];
+        if flags & Flags::SLOT_WRITE_FLAG.bits() == 0 {
+            return Ok(());
+        }
+
+        let seq = flags & 0x1f;
+        if seq == RESET_SEQ {
+            self.response.clear();
+            self.response_seq = None;
+            return Ok(());
+        }
+
+        let offset = seq as usize * 7;
+        if offset + 7 > FRAME_SIZE {
+            return Err(YubicoError::CanNotWriteToDevice);
+        }
+        if seq == 0 {
+            self.frame = [0; FRAME_SIZE];
+            self.response.clear();
+            self.response_seq = None;
+        }
+        self.frame[offset..offset + 7].copy_from_slice(&packet[..7]);
+
+        if offset + 7 == FRAME_SIZE {
+            self.process_frame()?
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Slot};
+    use crate::configure::DeviceModeConfig;
+    use crate::hmacmode::HmacKey;
+    use crate::Yubico;
+
+    const SW_WRONG_DATA: [u8; 2] = [0x6a, 0x80];
+    const SW_CONDITIONS_NOT_SATISFIED: [u8; 2] = [0x69, 0x85];
+
+    /// An OTP applet with an empty slot 1, rejecting writes if asked to.
+    struct MockCard {
+        status: Vec<u8>,
+        reject_writes: bool,
+    }
+
+    impl MockCard {
+        fn new() -> Self {
+            MockCard {
+                status: vec![5, 4, 3, 1, 0, 0],
+                reject_writes: false,
+            }
+        }
+    }
+
+    impl SmartCard for MockCard {
+        fn transmit(&mut self, apdu: &[u8]) -> Result<Vec<u8>, YubicoError> {
+            let mut response = match (apdu[1], apdu[2]) {
+                (INS_SELECT, _) => self.status.clone(),
+                (INS_CONFIG, p1) if p1 == Command::DeviceSerial as u8 => {
+                    1_234_567u32.to_be_bytes().to_vec()
+                }
+                (INS_CONFIG, p1) if p1 == Command::Configuration2 as u8 => {
+                    if self.reject_writes {
+                        return Ok(SW_WRONG_DATA.to_vec());
+                    }
+                    self.status[3] += 1;
+                    self.status.clone()
+                }
+                _ => return Ok(SW_CONDITIONS_NOT_SATISFIED.to_vec()),
+            };
+            response.extend_from_slice(&SW_OK.to_be_bytes());
+            Ok(response)
+        }
+    }
+
+    fn hmac_config() -> DeviceModeConfig {
+        let mut device_config = DeviceModeConfig::default();
+        device_config.challenge_response_hmac(
+            &HmacKey::from_slice(b"my_awesome_secret_20"),
+            true,
+            false,
+        );
+        device_config
+    }
+
+    #[test]
+    fn reads_the_serial() {
+        let mut yubi = Yubico::with_transport(CcidTransport::new(MockCard::new()));
+        assert_eq!(
+            yubi.read_serial_number(Config::default_config()).unwrap(),
+            1_234_567
+        );
+    }
+
+    #[test]
+    fn writes_a_slot() {
+        let mut yubi = Yubico::with_transport(CcidTransport::new(MockCard::new()));
+        let config = Config::default_config().set_command(Command::Configuration2);
+        yubi.write_config(config, &mut hmac_config()).unwrap();
+        assert_eq!(yubi.transport.card().status[3], 2);
+    }
+
+    #[test]
+    fn rejected_write_is_not_written() {
+        let mut card = MockCard::new();
+        card.reject_writes = true;
+        let mut yubi = Yubico::with_transport(CcidTransport::new(card));
+        let config = Config::default_config().set_command(Command::Configuration2);
+        match yubi.write_config(config, &mut hmac_config()) {
+            Err(YubicoError::ConfigNotWritten) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn challenge_to_an_empty_slot_times_out() {
+        let mut yubi = Yubico::with_transport(CcidTransport::new(MockCard::new()));
+        let config = Config::default_config().set_slot(Slot::Slot1);
+        match yubi.challenge_response_hmac(b"challenge", config) {
+            Err(YubicoError::Timeout) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn version_only_status_is_rejected() {
+        let mut card = MockCard::new();
+        card.status.truncate(3);
+        let mut transport = CcidTransport::new(card);
+        let device = transport.devices().unwrap().remove(0);
+        match transport.open(&device) {
+            Err(YubicoError::InvalidResponse) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -1,4 +1,8 @@
+use std::fmt;
 use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Slot {
@@ -9,6 +13,7 @@ pub enum Slot {
 impl Slot {
     /// Parses a slot number from a slice.
     /// Returns None if the slot number is invalid.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(slot_number: &str) -> Option<Slot> {
         if slot_number == "1" {
             return Some(Slot::Slot1);
@@ -38,6 +43,19 @@ pub enum Mode {
     Otp,
 }
 
+/// The USB interfaces exposed by a YubiKey NEO or YubiKey 4.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum DeviceMode {
+    Otp = 0x00,
+    Ccid = 0x01,
+    OtpCcid = 0x02,
+    U2f = 0x03,
+    OtpU2f = 0x04,
+    U2fCcid = 0x05,
+    OtpU2fCcid = 0x06,
+}
+
 /// From the Validation Protocol documentation:
 ///
 /// A value 0 to 100 indicating percentage of syncing required by client,
@@ -78,8 +96,13 @@ pub enum Command {
     Update1 = 0x04,
     Update2 = 0x05,
     Swap = 0x06,
+    Ndef1 = 0x08,
+    Ndef2 = 0x09,
     DeviceSerial = 0x10,
     DeviceConfig = 0x11,
+    ScanMap = 0x12,
+    DeviceInfo = 0x13,
+    WriteDeviceConfig = 0x15,
     ChallengeOtp1 = 0x20,
     ChallengeOtp2 = 0x28,
     ChallengeHmac1 = 0x30,
@@ -94,6 +117,17 @@ pub struct Config {
     pub slot: Slot,
     pub mode: Mode,
     pub command: Command,
+    /// Selects the device by serial number instead of product id.
+    pub serial: Option<u32>,
+    /// Selects the device by USB bus number and address instead of product id.
+    pub bus_address: Option<(u8, u8)>,
+    /// Maximum time an operation waits for the device, touch included.
+    /// `None` waits forever.
+    pub timeout: Option<Duration>,
+    /// Aborts the operation waiting for the device once cancelled.
+    pub cancellation: Option<CancellationToken>,
+    /// Notified while a challenge waits for the key to be touched.
+    pub touch_callback: Option<TouchCallback>,
 }
 
 #[allow(dead_code)]
@@ -106,6 +140,11 @@ impl Config {
             slot: Slot::Slot1,
             mode: Mode::Sha1,
             command: Command::ChallengeHmac1,
+            serial: None,
+            bus_address: None,
+            timeout: None,
+            cancellation: None,
+            touch_callback: None,
         }
     }
 
@@ -119,6 +158,34 @@ impl Config {
         self
     }
 
+    pub fn set_serial(mut self, serial: u32) -> Self {
+        self.serial = Some(serial);
+        self
+    }
+
+    pub fn set_bus_address(mut self, bus_number: u8, address: u8) -> Self {
+        self.bus_address = Some((bus_number, address));
+        self
+    }
+
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn set_cancellation_token(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = Some(cancellation);
+        self
+    }
+
+    pub fn set_touch_callback<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(TouchEvent) + Send + Sync + 'static,
+    {
+        self.touch_callback = Some(TouchCallback(Arc::new(callback)));
+        self
+    }
+
     pub fn set_variable_size(mut self, variable: bool) -> Self {
         self.variable = variable;
         self
@@ -139,3 +206,65 @@ impl Config {
         self
     }
 }
+
+/// Cancels the operations of the configurations it is set on, from
+/// another thread. Once cancelled, a token stays cancelled: use a new
+/// one for the next operations.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// The progress of a challenge sent to a slot that requires a touch
+/// (`ConfigFlags::CHAL_BTN_TRIG`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TouchEvent {
+    /// The key blinks, waiting to be touched.
+    Waiting,
+    /// The key has been touched, and answers the challenge.
+    Touched,
+    /// Nobody touched the key in time.
+    TimedOut,
+    /// The operation was cancelled while the key waited to be touched.
+    Cancelled,
+}
+
+/// A function called with the `TouchEvent`s of an operation.
+#[derive(Clone)]
+pub struct TouchCallback(Arc<dyn Fn(TouchEvent) + Send + Sync>);
+
+impl TouchCallback {
+    pub fn call(&self, event: TouchEvent) {
+        (self.0)(event)
+    }
+}
+
+impl fmt::Debug for TouchCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("TouchCallback")
+    }
+}
+
+impl PartialEq for TouchCallback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
@@ -1,14 +1,21 @@
 use crate::config::Command;
 use crate::hmacmode::HmacKey;
+use crate::keyboard::KeyboardLayout;
 use crate::manager::Frame;
 use crate::otpmode::Aes128Key;
 use crate::sec::crc16;
+use crate::yubicoerror::YubicoError;
 use std;
 
 const FIXED_SIZE: usize = 16;
 const UID_SIZE: usize = 6;
 const KEY_SIZE: usize = 16;
 const ACC_CODE_SIZE: usize = 6;
+const SCAN_CODES_SIZE: usize = FIXED_SIZE + UID_SIZE + KEY_SIZE;
+const OATH_TOKEN_ID_SIZE: usize = 6;
+const OATH_MAX_IMF: u32 = 0xffff0;
+/// The MUI is output as 8 decimal digits.
+const OATH_MAX_MUI: u32 = 99_999_999;
 
 /// The configuration of a YubiKey.
 #[repr(C)]
@@ -43,11 +50,22 @@ impl std::default::Default for DeviceModeConfig {
     }
 }
 
-const SIZEOF_CONFIG: usize = 52;
+pub(crate) const SIZEOF_CONFIG: usize = 52;
 
 impl DeviceModeConfig {
     #[doc(hidden)]
     pub fn to_frame(&mut self, command: Command) -> Frame {
+        self.to_frame_with_access_code(command, None)
+    }
+
+    /// Builds the frame writing this configuration, followed by the
+    /// current access code of the slot if it is protected.
+    #[doc(hidden)]
+    pub fn to_frame_with_access_code(
+        &mut self,
+        command: Command,
+        access_code: Option<&[u8; ACC_CODE_SIZE]>,
+    ) -> Frame {
         let mut payload = [0; 64];
         // First set CRC.
         self.crc = {
@@ -65,10 +83,19 @@ impl DeviceModeConfig {
             std::slice::from_raw_parts(self as *const DeviceModeConfig as *const u8, SIZEOF_CONFIG)
         };
         payload[..SIZEOF_CONFIG].clone_from_slice(s);
+        if let Some(code) = access_code {
+            payload[SIZEOF_CONFIG..SIZEOF_CONFIG + ACC_CODE_SIZE].copy_from_slice(code);
+        }
 
         Frame::new(payload, command)
     }
 
+    /// Protects the slot with an access code, which will have to be
+    /// given to reprogram, update, swap or delete it.
+    pub fn set_access_code(&mut self, access_code: &[u8; ACC_CODE_SIZE]) {
+        self.acc_code.copy_from_slice(access_code);
+    }
+
     /// Sets the configuration in challenge-response, HMAC-SHA1
     /// mode. This mode has two sub-modes: if `variable` is `true`,
     /// the challenges can be of variable length up to 63 bytes. Else,
@@ -120,9 +147,251 @@ impl DeviceModeConfig {
         self.uid.copy_from_slice(priv_id);
         self.key.copy_from_slice(&secret.0);
     }
+
+    /// Sets the configuration in Yubico OTP mode, where the key types
+    /// a one-time password on each button press. The `public_id` (up
+    /// to 16 bytes) is sent in clear before the encrypted token, and
+    /// `output` selects the tabs, delays and carriage return appended
+    /// around it; other ticket flags are ignored. The serial number is
+    /// left visible over the API.
+    pub fn yubico_otp(
+        &mut self,
+        public_id: &[u8],
+        private_id: &[u8; 6],
+        secret: &Aes128Key,
+        output: TicketFlags,
+    ) -> Result<(), YubicoError> {
+        if public_id.len() > FIXED_SIZE {
+            return Err(YubicoError::PublicIdTooLong);
+        }
+
+        self.tkt_flags = TicketFlags::empty();
+        self.cfg_flags = ConfigFlags::empty();
+        self.ext_flags = ExtendedFlags::SERIAL_API_VISIBLE;
+
+        self.tkt_flags.insert(
+            output
+                & (TicketFlags::TAB_FIRST
+                    | TicketFlags::APPEND_TAB1
+                    | TicketFlags::APPEND_TAB2
+                    | TicketFlags::APPEND_DELAY1
+                    | TicketFlags::APPEND_DELAY2
+                    | TicketFlags::APPEND_CR),
+        );
+
+        self.fixed = [0; FIXED_SIZE];
+        self.fixed[..public_id.len()].copy_from_slice(public_id);
+        self.fixed_size = public_id.len() as u8;
+        self.uid.copy_from_slice(private_id);
+        self.key.copy_from_slice(&secret.0);
+        Ok(())
+    }
+
+    /// Sets the configuration in OATH-HOTP mode, where the key types
+    /// a 6 or 8 digit HOTP code, followed by a carriage return, on
+    /// each button press. The code is optionally prefixed with
+    /// `token_id`, and the counter starts at `moving_factor_seed`. The
+    /// serial number is left visible over the API.
+    ///
+    /// `digits` must be 6 or 8, `moving_factor_seed` a multiple of 16
+    /// up to 0xffff0, and the MUI of `token_id` at most 8 digits long.
+    pub fn oath_hotp(
+        &mut self,
+        secret: &HmacKey,
+        digits: u8,
+        token_id: Option<&OathTokenId>,
+        moving_factor_seed: u32,
+    ) -> Result<(), YubicoError> {
+        if digits != 6 && digits != 8 {
+            return Err(YubicoError::InvalidOathDigits(digits));
+        }
+        if moving_factor_seed & 0xf != 0 || moving_factor_seed > OATH_MAX_IMF {
+            return Err(YubicoError::InvalidMovingFactor(moving_factor_seed));
+        }
+        if let Some(id) = token_id {
+            if id.mui > OATH_MAX_MUI {
+                return Err(YubicoError::TokenIdTooLong);
+            }
+        }
+
+        self.tkt_flags = TicketFlags::empty();
+        self.cfg_flags = ConfigFlags::empty();
+        self.ext_flags = ExtendedFlags::SERIAL_API_VISIBLE;
+
+        self.tkt_flags
+            .insert(TicketFlags::OATH_HOTP | TicketFlags::APPEND_CR);
+        if digits == 8 {
+            self.cfg_flags.insert(ConfigFlags::OATH_HOTP8)
+        }
+
+        self.fixed = [0; FIXED_SIZE];
+        self.fixed_size = 0;
+        if let Some(token_id) = token_id {
+            // OMP and TT in modhex, MUI in decimal.
+            self.cfg_flags.insert(ConfigFlags::OATH_FIXED_MODHEX2);
+            self.fixed[..OATH_TOKEN_ID_SIZE].copy_from_slice(&token_id.to_fixed());
+            self.fixed_size = OATH_TOKEN_ID_SIZE as u8;
+        }
+
+        let (a, b) = secret.0.split_at(16);
+        self.key.copy_from_slice(a);
+        self.uid[..4].copy_from_slice(b);
+        // The firmware stores the seed divided by 16, big-endian.
+        let imf = (moving_factor_seed / 16) as u16;
+        self.uid[4..].copy_from_slice(&imf.to_be_bytes());
+        Ok(())
+    }
+
+    /// Sets the configuration in static password mode, where the key
+    /// types `password`, followed by a carriage return, on each button
+    /// press. The password is converted to scan codes for `layout`, and
+    /// can be up to 38 characters long. The serial number is left
+    /// visible over the API.
+    pub fn static_password(
+        &mut self,
+        password: &str,
+        layout: KeyboardLayout,
+    ) -> Result<(), YubicoError> {
+        let scan_codes = layout.encode(password)?;
+        if scan_codes.len() > SCAN_CODES_SIZE {
+            return Err(YubicoError::PasswordTooLong);
+        }
+
+        self.tkt_flags = TicketFlags::empty();
+        self.cfg_flags = ConfigFlags::empty();
+        self.ext_flags = ExtendedFlags::SERIAL_API_VISIBLE;
+
+        self.tkt_flags.insert(TicketFlags::APPEND_CR);
+        self.cfg_flags.insert(ConfigFlags::SHORT_TICKET);
+
+        // The scan codes run over the fixed, uid and key fields, and
+        // are terminated by a zero.
+        let mut buf = [0; SCAN_CODES_SIZE];
+        buf[..scan_codes.len()].copy_from_slice(&scan_codes);
+        let (fixed, rest) = buf.split_at(FIXED_SIZE);
+        let (uid, key) = rest.split_at(UID_SIZE);
+        self.fixed.copy_from_slice(fixed);
+        self.uid.copy_from_slice(uid);
+        self.key.copy_from_slice(key);
+        self.fixed_size = FIXED_SIZE as u8;
+        Ok(())
+    }
+}
+
+/// An OATH token identifier, typed before each HOTP code.
+///
+/// It is made of a manufacturer prefix (OMP) and a token type (TT),
+/// both output in modhex, followed by a manufacturer unique identifier
+/// (MUI) output as 8 decimal digits, so at most 99999999.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OathTokenId {
+    pub omp: u8,
+    pub tt: u8,
+    pub mui: u32,
+}
+
+impl OathTokenId {
+    /// The identifier used by the Yubico tools: "ubhe" followed by
+    /// the serial number of the key.
+    pub fn yubico(serial: u32) -> Self {
+        OathTokenId {
+            omp: 0xe1,
+            tt: 0x63,
+            mui: serial,
+        }
+    }
+
+    fn to_fixed(self) -> [u8; OATH_TOKEN_ID_SIZE] {
+        let mut fixed = [self.omp, self.tt, 0, 0, 0, 0];
+        let mut mui = self.mui;
+        for b in fixed[2..].iter_mut().rev() {
+            *b = (((mui / 10 % 10) << 4) | (mui % 10)) as u8;
+            mui /= 100;
+        }
+        fixed
+    }
+}
+
+/// A partial configuration, changing the output settings of a slot
+/// without touching its secrets. Only the flags in the `UPDATE_MASK`
+/// of each flag type can be updated, the others are dropped; and the
+/// slot must have been configured with `ExtendedFlags::ALLOW_UPDATE`.
+///
+/// An update replaces all the updatable flags of the slot, those left
+/// unset included. The default update only sets
+/// `ExtendedFlags::SERIAL_API_VISIBLE` and `ExtendedFlags::ALLOW_UPDATE`,
+/// so that the slot can be updated again and the key still be found
+/// by its serial.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpdateConfig {
+    tkt_flags: TicketFlags,
+    cfg_flags: ConfigFlags,
+    ext_flags: ExtendedFlags,
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        UpdateConfig {
+            tkt_flags: TicketFlags::empty(),
+            cfg_flags: ConfigFlags::empty(),
+            ext_flags: ExtendedFlags::SERIAL_API_VISIBLE | ExtendedFlags::ALLOW_UPDATE,
+        }
+    }
+}
+
+impl UpdateConfig {
+    pub fn set_ticket_flags(mut self, flags: TicketFlags) -> Self {
+        self.tkt_flags = flags & TicketFlags::UPDATE_MASK;
+        self
+    }
+
+    pub fn set_config_flags(mut self, flags: ConfigFlags) -> Self {
+        self.cfg_flags = flags & ConfigFlags::UPDATE_MASK;
+        self
+    }
+
+    pub fn set_extended_flags(mut self, flags: ExtendedFlags) -> Self {
+        self.ext_flags = flags & ExtendedFlags::UPDATE_MASK;
+        self
+    }
+
+    pub fn ticket_flags(&self) -> TicketFlags {
+        self.tkt_flags
+    }
+
+    pub fn config_flags(&self) -> ConfigFlags {
+        self.cfg_flags
+    }
+
+    pub fn extended_flags(&self) -> ExtendedFlags {
+        self.ext_flags
+    }
+
+    /// Builds the update frame. `access_code` is the current access
+    /// code of the slot, and `new_access_code` the one it gets after
+    /// the update (`None` removes it).
+    #[doc(hidden)]
+    pub fn to_frame(
+        &self,
+        command: Command,
+        access_code: Option<&[u8; ACC_CODE_SIZE]>,
+        new_access_code: Option<&[u8; ACC_CODE_SIZE]>,
+    ) -> Frame {
+        let mut config = DeviceModeConfig {
+            tkt_flags: self.tkt_flags,
+            cfg_flags: self.cfg_flags,
+            ext_flags: self.ext_flags,
+            ..Default::default()
+        };
+        if let Some(code) = new_access_code {
+            config.set_access_code(code);
+        }
+        config.to_frame_with_access_code(command, access_code)
+    }
 }
 
 bitflags! {
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
     pub struct TicketFlags: u8 {
         const TAB_FIRST = 0x1;
         const APPEND_TAB1 = 0x2;
@@ -136,7 +405,13 @@ bitflags! {
     }
 }
 
+impl TicketFlags {
+    /// The flags that can be changed by an update.
+    pub const UPDATE_MASK: TicketFlags = TicketFlags::from_bits_retain(0x3f);
+}
+
 bitflags! {
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
     pub struct ConfigFlags: u8 {
         // Yubikey 1.0
         const SEND_REF = 0x1;
@@ -163,7 +438,13 @@ bitflags! {
     }
 }
 
+impl ConfigFlags {
+    /// The flags that can be changed by an update.
+    pub const UPDATE_MASK: ConfigFlags = ConfigFlags::from_bits_retain(0x0c);
+}
+
 bitflags! {
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
     pub struct ExtendedFlags: u8 {
         const SERIAL_BTN_VISIBLE = 0x01;
         const SERIAL_USB_VISIBLE = 0x02;
@@ -173,5 +454,94 @@ bitflags! {
         const FAST_TRIG = 0x10;
         const ALLOW_UPDATE = 0x20;
         const DORMANT = 0x40;
+        // YubiKey >= 2.4
+        const LED_INV = 0x80;
+    }
+}
+
+impl ExtendedFlags {
+    /// The flags that can be changed by an update.
+    pub const UPDATE_MASK: ExtendedFlags = ExtendedFlags::from_bits_retain(0xff);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard::SHIFT;
+
+    const SECRET: &[u8; 20] = b"my_awesome_secret_20";
+
+    #[test]
+    fn oath_hotp_layout() {
+        let mut config = DeviceModeConfig::default();
+        let token_id = OathTokenId::yubico(1_234_567);
+        config
+            .oath_hotp(&HmacKey::from_slice(SECRET), 8, Some(&token_id), 0x12340)
+            .unwrap();
+
+        // OMP and TT as is, then the MUI in BCD, right-aligned.
+        assert_eq!(config.fixed[..6], [0xe1, 0x63, 0x01, 0x23, 0x45, 0x67]);
+        assert_eq!(config.fixed_size, 6);
+        assert_eq!(config.key, SECRET[..16]);
+        assert_eq!(config.uid[..4], SECRET[16..]);
+        // The moving factor seed divided by 16, big-endian.
+        assert_eq!(config.uid[4..], [0x12, 0x34]);
+        assert!(config
+            .cfg_flags
+            .contains(ConfigFlags::OATH_HOTP8 | ConfigFlags::OATH_FIXED_MODHEX2));
+    }
+
+    #[test]
+    fn oath_hotp_rejects_invalid_parameters() {
+        let mut config = DeviceModeConfig::default();
+        let secret = HmacKey::from_slice(SECRET);
+        match config.oath_hotp(&secret, 7, None, 0) {
+            Err(YubicoError::InvalidOathDigits(7)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match config.oath_hotp(&secret, 6, None, 0x12341) {
+            Err(YubicoError::InvalidMovingFactor(0x12341)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let token_id = OathTokenId::yubico(OATH_MAX_MUI + 1);
+        match config.oath_hotp(&secret, 6, Some(&token_id), 0) {
+            Err(YubicoError::TokenIdTooLong) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn static_password_spans_fixed_uid_and_key() {
+        let mut config = DeviceModeConfig::default();
+        config
+            .static_password("abcdefghijklmnopqrstuvwxyz0123456789AB", KeyboardLayout::Us)
+            .unwrap();
+
+        let mut scan_codes: Vec<u8> = (0x04..=0x1d).collect();
+        scan_codes.push(0x27);
+        scan_codes.extend(0x1e..=0x26);
+        scan_codes.extend([SHIFT | 0x04, SHIFT | 0x05]);
+        assert_eq!(config.fixed, scan_codes[..16]);
+        assert_eq!(config.uid, scan_codes[16..22]);
+        assert_eq!(config.key, scan_codes[22..]);
+        assert_eq!(config.fixed_size, 16);
+    }
+
+    #[test]
+    fn static_password_is_zero_terminated() {
+        let mut config = DeviceModeConfig::default();
+        config.static_password("ab", KeyboardLayout::Us).unwrap();
+        assert_eq!(config.fixed[..3], [0x04, 0x05, 0]);
+        assert_eq!(config.uid, [0; UID_SIZE]);
+        assert_eq!(config.key, [0; KEY_SIZE]);
+    }
+
+    #[test]
+    fn static_password_too_long() {
+        let mut config = DeviceModeConfig::default();
+        match config.static_password(&"a".repeat(SCAN_CODES_SIZE + 1), KeyboardLayout::Us) {
+            Err(YubicoError::PasswordTooLong) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
     }
 }
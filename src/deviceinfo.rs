@@ -0,0 +1,335 @@
+//! Device information of YubiKey 4.1 and later, encoded as TLVs.
+
+use crate::yubicoerror::YubicoError;
+
+pub(crate) const TAG_USB_SUPPORTED: u8 = 0x01;
+pub(crate) const TAG_SERIAL: u8 = 0x02;
+pub(crate) const TAG_USB_ENABLED: u8 = 0x03;
+pub(crate) const TAG_FORM_FACTOR: u8 = 0x04;
+pub(crate) const TAG_VERSION: u8 = 0x05;
+pub(crate) const TAG_AUTO_EJECT_TIMEOUT: u8 = 0x06;
+pub(crate) const TAG_CHALRESP_TIMEOUT: u8 = 0x07;
+pub(crate) const TAG_DEVICE_FLAGS: u8 = 0x08;
+pub(crate) const TAG_CONFIG_LOCK: u8 = 0x0a;
+pub(crate) const TAG_UNLOCK: u8 = 0x0b;
+pub(crate) const TAG_REBOOT: u8 = 0x0c;
+pub(crate) const TAG_NFC_SUPPORTED: u8 = 0x0d;
+pub(crate) const TAG_NFC_ENABLED: u8 = 0x0e;
+pub(crate) const TAG_MORE_DATA: u8 = 0x10;
+pub(crate) const TAG_FIPS_CAPABLE: u8 = 0x14;
+pub(crate) const TAG_FIPS_APPROVED: u8 = 0x15;
+
+const FORM_FACTOR_MASK: u8 = 0x0f;
+const FORM_FACTOR_FIPS: u8 = 0x80;
+const FORM_FACTOR_SKY: u8 = 0x40;
+
+bitflags! {
+    /// The applications of a YubiKey.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct Capabilities: u16 {
+        const OTP = 0x01;
+        const U2F = 0x02;
+        const OPENPGP = 0x08;
+        const PIV = 0x10;
+        const OATH = 0x20;
+        const HSMAUTH = 0x100;
+        const FIDO2 = 0x200;
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct DeviceFlags: u8 {
+        const REMOTE_WAKEUP = 0x40;
+        const EJECT = 0x80;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FormFactor {
+    Unknown,
+    UsbAKeychain,
+    UsbANano,
+    UsbCKeychain,
+    UsbCNano,
+    UsbCLightning,
+    UsbABio,
+    UsbCBio,
+}
+
+impl FormFactor {
+    fn from_byte(b: u8) -> Self {
+        match b & FORM_FACTOR_MASK {
+            0x01 => FormFactor::UsbAKeychain,
+            0x02 => FormFactor::UsbANano,
+            0x03 => FormFactor::UsbCKeychain,
+            0x04 => FormFactor::UsbCNano,
+            0x05 => FormFactor::UsbCLightning,
+            0x06 => FormFactor::UsbABio,
+            0x07 => FormFactor::UsbCBio,
+            _ => FormFactor::Unknown,
+        }
+    }
+}
+
+/// The information returned by the "read device info" command.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceInfo {
+    pub serial: Option<u32>,
+    pub version: Option<(u8, u8, u8)>,
+    pub form_factor: FormFactor,
+    pub usb_supported: Capabilities,
+    pub usb_enabled: Capabilities,
+    pub nfc_supported: Capabilities,
+    pub nfc_enabled: Capabilities,
+    /// Set on YubiKey FIPS (4 series).
+    pub is_fips: bool,
+    /// Set on Security Keys.
+    pub is_sky: bool,
+    /// Applications that can run in FIPS approved mode (5.7 and later).
+    pub fips_capable: Capabilities,
+    /// Applications currently in FIPS approved mode (5.7 and later).
+    pub fips_approved: Capabilities,
+    /// Set if the configuration is protected by a lock code.
+    pub is_locked: bool,
+    /// Auto-eject timeout of the CCID interface, in seconds.
+    pub auto_eject_timeout: u16,
+    /// Touch timeout of challenge-response, in seconds.
+    pub challenge_response_timeout: u8,
+    pub device_flags: DeviceFlags,
+}
+
+impl DeviceInfo {
+    /// Parses the TLVs of the device information.
+    pub fn parse(data: &[u8]) -> Result<Self, YubicoError> {
+        let mut info = DeviceInfo {
+            serial: None,
+            version: None,
+            form_factor: FormFactor::Unknown,
+            usb_supported: Capabilities::empty(),
+            usb_enabled: Capabilities::empty(),
+            nfc_supported: Capabilities::empty(),
+            nfc_enabled: Capabilities::empty(),
+            is_fips: false,
+            is_sky: false,
+            fips_capable: Capabilities::empty(),
+            fips_approved: Capabilities::empty(),
+            is_locked: false,
+            auto_eject_timeout: 0,
+            challenge_response_timeout: 0,
+            device_flags: DeviceFlags::empty(),
+        };
+
+        for (tag, value) in parse_tlvs(data)? {
+            match tag {
+                TAG_USB_SUPPORTED => info.usb_supported = capabilities(value),
+                TAG_USB_ENABLED => info.usb_enabled = capabilities(value),
+                TAG_NFC_SUPPORTED => info.nfc_supported = capabilities(value),
+                TAG_NFC_ENABLED => info.nfc_enabled = capabilities(value),
+                TAG_FIPS_CAPABLE => info.fips_capable = fips_capabilities(value),
+                TAG_FIPS_APPROVED => info.fips_approved = fips_capabilities(value),
+                TAG_SERIAL => info.serial = Some(be_int(value)),
+                TAG_VERSION if value.len() == 3 => {
+                    info.version = Some((value[0], value[1], value[2]))
+                }
+                TAG_FORM_FACTOR if !value.is_empty() => {
+                    info.form_factor = FormFactor::from_byte(value[0]);
+                    info.is_fips = value[0] & FORM_FACTOR_FIPS != 0;
+                    info.is_sky = value[0] & FORM_FACTOR_SKY != 0;
+                }
+                TAG_CONFIG_LOCK => info.is_locked = be_int(value) != 0,
+                TAG_AUTO_EJECT_TIMEOUT => info.auto_eject_timeout = be_int(value) as u16,
+                TAG_CHALRESP_TIMEOUT => info.challenge_response_timeout = be_int(value) as u8,
+                TAG_DEVICE_FLAGS => {
+                    info.device_flags = DeviceFlags::from_bits_truncate(be_int(value) as u8)
+                }
+                _ => {}
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+/// The length of the configuration lock code.
+pub const LOCK_CODE_SIZE: usize = 16;
+
+/// A change to the device configuration of a YubiKey 5. Only the
+/// settings that are set are written.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceConfigUpdate {
+    pub usb_enabled: Option<Capabilities>,
+    pub nfc_enabled: Option<Capabilities>,
+    /// Auto-eject timeout of the CCID interface, in seconds.
+    pub auto_eject_timeout: Option<u16>,
+    /// Touch timeout of challenge-response, in seconds.
+    pub challenge_response_timeout: Option<u8>,
+    pub device_flags: Option<DeviceFlags>,
+    /// The current lock code, if the configuration is locked.
+    pub lock_code: Option<[u8; LOCK_CODE_SIZE]>,
+    /// A new lock code; all zeros removes the lock.
+    pub new_lock_code: Option<[u8; LOCK_CODE_SIZE]>,
+    /// Reboots the key after the change, applying it at once.
+    pub reboot: bool,
+}
+
+impl DeviceConfigUpdate {
+    pub fn set_usb_enabled(mut self, capabilities: Capabilities) -> Self {
+        self.usb_enabled = Some(capabilities);
+        self
+    }
+
+    pub fn set_nfc_enabled(mut self, capabilities: Capabilities) -> Self {
+        self.nfc_enabled = Some(capabilities);
+        self
+    }
+
+    pub fn set_auto_eject_timeout(mut self, timeout: u16) -> Self {
+        self.auto_eject_timeout = Some(timeout);
+        self
+    }
+
+    pub fn set_challenge_response_timeout(mut self, timeout: u8) -> Self {
+        self.challenge_response_timeout = Some(timeout);
+        self
+    }
+
+    pub fn set_device_flags(mut self, flags: DeviceFlags) -> Self {
+        self.device_flags = Some(flags);
+        self
+    }
+
+    pub fn set_lock_code(mut self, lock_code: &[u8; LOCK_CODE_SIZE]) -> Self {
+        self.lock_code = Some(*lock_code);
+        self
+    }
+
+    pub fn set_new_lock_code(mut self, lock_code: &[u8; LOCK_CODE_SIZE]) -> Self {
+        self.new_lock_code = Some(*lock_code);
+        self
+    }
+
+    pub fn set_reboot(mut self, reboot: bool) -> Self {
+        self.reboot = reboot;
+        self
+    }
+
+    /// Encodes the update as a length byte followed by TLVs.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut tlvs = Vec::new();
+        let mut push = |tag: u8, value: &[u8]| {
+            tlvs.push(tag);
+            tlvs.push(value.len() as u8);
+            tlvs.extend_from_slice(value);
+        };
+        if let Some(capabilities) = self.usb_enabled {
+            push(TAG_USB_ENABLED, &capabilities.bits().to_be_bytes())
+        }
+        if let Some(capabilities) = self.nfc_enabled {
+            push(TAG_NFC_ENABLED, &capabilities.bits().to_be_bytes())
+        }
+        if let Some(timeout) = self.auto_eject_timeout {
+            push(TAG_AUTO_EJECT_TIMEOUT, &timeout.to_be_bytes())
+        }
+        if let Some(timeout) = self.challenge_response_timeout {
+            push(TAG_CHALRESP_TIMEOUT, &[timeout])
+        }
+        if let Some(flags) = self.device_flags {
+            push(TAG_DEVICE_FLAGS, &[flags.bits()])
+        }
+        if let Some(ref code) = self.new_lock_code {
+            push(TAG_CONFIG_LOCK, code)
+        }
+        if let Some(ref code) = self.lock_code {
+            push(TAG_UNLOCK, code)
+        }
+        if self.reboot {
+            push(TAG_REBOOT, &[])
+        }
+
+        let mut data = vec![tlvs.len() as u8];
+        data.extend_from_slice(&tlvs);
+        data
+    }
+}
+
+fn be_int(value: &[u8]) -> u32 {
+    value.iter().fold(0, |acc, &b| (acc << 8) | b as u32)
+}
+
+fn capabilities(value: &[u8]) -> Capabilities {
+    Capabilities::from_bits_truncate(be_int(value) as u16)
+}
+
+/// The FIPS tags use their own bits: FIDO2, PIV, OpenPGP, OATH and
+/// HSMAuth, from bit 0 up.
+fn fips_capabilities(value: &[u8]) -> Capabilities {
+    const FIPS_BITS: [Capabilities; 5] = [
+        Capabilities::FIDO2,
+        Capabilities::PIV,
+        Capabilities::OPENPGP,
+        Capabilities::OATH,
+        Capabilities::HSMAUTH,
+    ];
+    let bits = be_int(value);
+    FIPS_BITS
+        .iter()
+        .enumerate()
+        .filter(|&(bit, _)| bits & (1 << bit) != 0)
+        .fold(Capabilities::empty(), |acc, (_, &capability)| {
+            acc | capability
+        })
+}
+
+/// Splits a buffer into (tag, value) pairs, with one-byte tags and
+/// lengths.
+pub(crate) fn parse_tlvs(mut data: &[u8]) -> Result<Vec<(u8, &[u8])>, YubicoError> {
+    let mut tlvs = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 2 || data.len() < 2 + data[1] as usize {
+            return Err(YubicoError::InvalidResponse);
+        }
+        let (tlv, rest) = data.split_at(2 + data[1] as usize);
+        tlvs.push((tlv[0], &tlv[2..]));
+        data = rest;
+    }
+    Ok(tlvs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fips_capabilities_use_their_own_bits() {
+        let info =
+            DeviceInfo::parse(&[TAG_FIPS_CAPABLE, 2, 0x00, 0x1f, TAG_FIPS_APPROVED, 1, 0x05])
+                .unwrap();
+        assert_eq!(
+            info.fips_capable,
+            Capabilities::FIDO2
+                | Capabilities::PIV
+                | Capabilities::OPENPGP
+                | Capabilities::OATH
+                | Capabilities::HSMAUTH
+        );
+        assert_eq!(
+            info.fips_approved,
+            Capabilities::FIDO2 | Capabilities::OPENPGP
+        );
+    }
+
+    #[test]
+    fn capabilities_use_the_usb_bits() {
+        let info = DeviceInfo::parse(&[TAG_USB_SUPPORTED, 2, 0x02, 0x3b]).unwrap();
+        assert_eq!(
+            info.usb_supported,
+            Capabilities::OTP
+                | Capabilities::U2F
+                | Capabilities::OPENPGP
+                | Capabilities::PIV
+                | Capabilities::OATH
+                | Capabilities::FIDO2
+        );
+    }
+}
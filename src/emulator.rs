@@ -0,0 +1,623 @@
+//! A software YubiKey speaking the OTP HID protocol.
+//!
+//! The emulator plugs into `Yubico::with_transport` and answers the same
+//! 8-byte feature reports as a real key, so provisioning and
+//! challenge-response can be exercised without hardware.
+
+use crate::config::{Command, Slot};
+use crate::configure::{ConfigFlags, ExtendedFlags, TicketFlags, SIZEOF_CONFIG};
+use crate::deviceinfo::{
+    parse_tlvs, Capabilities, LOCK_CODE_SIZE, TAG_AUTO_EJECT_TIMEOUT, TAG_CHALRESP_TIMEOUT,
+    TAG_CONFIG_LOCK, TAG_DEVICE_FLAGS, TAG_FORM_FACTOR, TAG_SERIAL, TAG_UNLOCK, TAG_USB_ENABLED,
+    TAG_USB_SUPPORTED, TAG_VERSION,
+};
+use crate::hmacmode::HmacKey;
+use crate::keyboard::{ScanMap, SCAN_MAP_SIZE};
+use crate::manager::Flags;
+use crate::ndef::SIZEOF_NDEF;
+use crate::sec::{crc16, hmac_sha1, CRC_RESIDUAL_OK};
+use crate::status::{CONFIG1_TOUCH, CONFIG1_VALID, CONFIG2_TOUCH, CONFIG2_VALID, LED_INV};
+use crate::transport::Transport;
+use crate::yubicoerror::YubicoError;
+use crate::{Device, VENDOR_ID};
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+
+const FRAME_SIZE: usize = 70;
+const PAYLOAD_SIZE: usize = 64;
+const ACC_CODE_OFFSET: usize = 38;
+const ACC_CODE_SIZE: usize = 6;
+const RESET_SEQ: u8 = 0x0f;
+/// Number of status reads a touch-triggered challenge waits before
+/// the emulated user touches the key, or the key gives up.
+const TOUCH_POLLS: u8 = 20;
+
+/// A slot configuration, as written by `DeviceModeConfig::to_frame`.
+struct SlotConfig([u8; SIZEOF_CONFIG]);
+
+impl SlotConfig {
+    fn uid(&self) -> &[u8] {
+        &self.0[16..22]
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.0[22..38]
+    }
+
+    fn acc_code(&self) -> &[u8] {
+        &self.0[ACC_CODE_OFFSET..ACC_CODE_OFFSET + ACC_CODE_SIZE]
+    }
+
+    fn ext_flags(&self) -> ExtendedFlags {
+        ExtendedFlags::from_bits_truncate(self.0[45])
+    }
+
+    fn tkt_flags(&self) -> TicketFlags {
+        TicketFlags::from_bits_truncate(self.0[46])
+    }
+
+    fn cfg_flags(&self) -> ConfigFlags {
+        ConfigFlags::from_bits_truncate(self.0[47])
+    }
+
+    fn is_challenge_response(&self, mode: ConfigFlags) -> bool {
+        self.tkt_flags().contains(TicketFlags::CHAL_RESP)
+            && self.cfg_flags().bits() & ConfigFlags::CHAL_HMAC.bits() == mode.bits()
+    }
+}
+
+/// An emulated YubiKey.
+pub struct Emulator {
+    serial: u32,
+    product_id: u16,
+    version: (u8, u8, u8),
+    pgm_seq: u8,
+    slots: [Option<SlotConfig>; 2],
+    opened: bool,
+    frame: [u8; FRAME_SIZE],
+    response: Vec<u8>,
+    response_seq: Option<usize>,
+    use_counter: u16,
+    session_counter: u8,
+    timestamp: u32,
+    usb_supported: Capabilities,
+    usb_enabled: Capabilities,
+    form_factor: u8,
+    auto_eject_timeout: u16,
+    challenge_response_timeout: u8,
+    device_flags: u8,
+    lock_code: Option<[u8; LOCK_CODE_SIZE]>,
+    ndef: [Option<[u8; SIZEOF_NDEF]>; 2],
+    scan_map: ScanMap,
+    touch: bool,
+    touch_wait: Option<u8>,
+}
+
+impl Emulator {
+    /// Creates a blank key with the given serial number.
+    pub fn new(serial: u32) -> Self {
+        Emulator {
+            serial,
+            product_id: 0x0407,
+            version: (5, 4, 3),
+            pgm_seq: 0,
+            slots: [None, None],
+            opened: false,
+            frame: [0; FRAME_SIZE],
+            response: Vec::new(),
+            response_seq: None,
+            use_counter: 1,
+            session_counter: 0,
+            timestamp: 0,
+            usb_supported: Capabilities::OTP
+                | Capabilities::U2F
+                | Capabilities::OPENPGP
+                | Capabilities::PIV
+                | Capabilities::OATH
+                | Capabilities::FIDO2,
+            usb_enabled: Capabilities::OTP
+                | Capabilities::U2F
+                | Capabilities::OPENPGP
+                | Capabilities::PIV
+                | Capabilities::OATH
+                | Capabilities::FIDO2,
+            form_factor: 0x01,
+            auto_eject_timeout: 0,
+            challenge_response_timeout: 15,
+            device_flags: 0,
+            lock_code: None,
+            ndef: [None, None],
+            scan_map: ScanMap::default(),
+            touch: true,
+            touch_wait: None,
+        }
+    }
+
+    /// Sets the firmware version reported by the key.
+    pub fn with_version(mut self, major: u8, minor: u8, build: u8) -> Self {
+        self.version = (major, minor, build);
+        self
+    }
+
+    /// Sets the USB product id reported by the key.
+    pub fn with_product_id(mut self, product_id: u16) -> Self {
+        self.product_id = product_id;
+        self
+    }
+
+    /// Sets whether the key is touched when a challenge waits for it.
+    pub fn with_touch(mut self, touch: bool) -> Self {
+        self.touch = touch;
+        self
+    }
+
+    /// Returns the serial number of the key.
+    pub fn serial(&self) -> u32 {
+        self.serial
+    }
+
+    /// Returns the programming sequence counter.
+    pub fn pgm_seq(&self) -> u8 {
+        self.pgm_seq
+    }
+
+    fn device(&self) -> Device {
+        Device {
+            product_id: self.product_id,
+            vendor_id: VENDOR_ID,
+            bus_number: 1,
+            address: 1,
+            serial: None,
+            version: None,
+        }
+    }
+
+    /// Returns the NDEF record programmed for a slot, as its length,
+    /// type and data bytes.
+    pub fn ndef(&self, slot: &Slot) -> Option<&[u8]> {
+        let ndef = match *slot {
+            Slot::Slot1 => self.ndef[0].as_ref(),
+            Slot::Slot2 => self.ndef[1].as_ref(),
+        };
+        ndef.map(|n| &n[..n[0] as usize + 2])
+    }
+
+    pub fn scan_map(&self) -> &ScanMap {
+        &self.scan_map
+    }
+
+    /// A blank key shows its serial; once configured, it answers the
+    /// serial query only if a slot allows it.
+    fn is_serial_visible(&self) -> bool {
+        self.slots.iter().all(|s| s.is_none())
+            || self
+                .slots
+                .iter()
+                .flatten()
+                .any(|s| s.ext_flags().contains(ExtendedFlags::SERIAL_API_VISIBLE))
+    }
+
+    fn touch_level(&self) -> u16 {
+        let mut level = 0;
+        let bits = [
+            (CONFIG1_VALID, CONFIG1_TOUCH),
+            (CONFIG2_VALID, CONFIG2_TOUCH),
+        ];
+        for (slot, &(valid, touch)) in self.slots.iter().zip(bits.iter()) {
+            if let Some(ref slot) = *slot {
+                level |= valid;
+                if !slot.tkt_flags().contains(TicketFlags::CHAL_RESP) {
+                    level |= touch
+                }
+                if slot.ext_flags().contains(ExtendedFlags::LED_INV) {
+                    level |= LED_INV
+                }
+            }
+        }
+        level
+    }
+
+    fn status(&self) -> [u8; 8] {
+        let touch = self.touch_level().to_le_bytes();
+        [
+            0,
+            self.version.0,
+            self.version.1,
+            self.version.2,
+            self.pgm_seq,
+            touch[0],
+            touch[1],
+            0,
+        ]
+    }
+
+    fn process_frame(&mut self) {
+        let payload = &self.frame[..PAYLOAD_SIZE];
+        let crc = u16::from_le_bytes([self.frame[65], self.frame[66]]);
+        if crc16(payload) != crc {
+            return;
+        }
+
+        let mut data = [0; PAYLOAD_SIZE];
+        data.copy_from_slice(payload);
+        let command = self.frame[64];
+
+        if command == Command::Configuration1 as u8 {
+            self.write_slot(0, &data)
+        } else if command == Command::Configuration2 as u8 {
+            self.write_slot(1, &data)
+        } else if command == Command::Update1 as u8 {
+            self.update_slot(0, &data)
+        } else if command == Command::Update2 as u8 {
+            self.update_slot(1, &data)
+        } else if command == Command::Swap as u8 {
+            self.swap_slots(&data)
+        } else if command == Command::DeviceInfo as u8 {
+            self.device_info()
+        } else if command == Command::DeviceConfig as u8 {
+            self.set_device_mode(&data)
+        } else if command == Command::WriteDeviceConfig as u8 {
+            self.write_device_config(&data)
+        } else if command == Command::Ndef1 as u8 {
+            self.write_ndef(0, &data)
+        } else if command == Command::Ndef2 as u8 {
+            self.write_ndef(1, &data)
+        } else if command == Command::ScanMap as u8 {
+            let mut map = [0; SCAN_MAP_SIZE];
+            map.copy_from_slice(&data[..SCAN_MAP_SIZE]);
+            self.scan_map = ScanMap::from_bytes(&map);
+            self.written()
+        } else if command == Command::DeviceSerial as u8 {
+            if self.is_serial_visible() {
+                let serial = self.serial.to_be_bytes();
+                self.respond(&serial)
+            }
+        } else if command == Command::ChallengeHmac1 as u8 {
+            self.challenge_hmac(0, &data)
+        } else if command == Command::ChallengeHmac2 as u8 {
+            self.challenge_hmac(1, &data)
+        } else if command == Command::ChallengeOtp1 as u8 {
+            self.challenge_otp(0, &data)
+        } else if command == Command::ChallengeOtp2 as u8 {
+            self.challenge_otp(1, &data)
+        }
+    }
+
+    fn write_slot(&mut self, slot: usize, data: &[u8; PAYLOAD_SIZE]) {
+        let (config, acc_code) = data.split_at(SIZEOF_CONFIG);
+
+        if let Some(ref current) = self.slots[slot] {
+            if current.acc_code() != &acc_code[..ACC_CODE_SIZE] {
+                return;
+            }
+        }
+
+        if config.iter().all(|&b| b == 0) {
+            self.slots[slot] = None;
+        } else if crc16(config) == CRC_RESIDUAL_OK {
+            let mut bytes = [0; SIZEOF_CONFIG];
+            bytes.copy_from_slice(config);
+            self.slots[slot] = Some(SlotConfig(bytes));
+        } else {
+            return;
+        }
+
+        self.written()
+    }
+
+    /// Advances the programming sequence after a successful write. Like
+    /// the firmware, the sequence stays at 0 while no slot is configured.
+    fn written(&mut self) {
+        if self.slots.iter().all(|s| s.is_none()) {
+            self.pgm_seq = 0
+        } else {
+            self.pgm_seq = self.pgm_seq.wrapping_add(1)
+        }
+    }
+
+    fn update_slot(&mut self, slot: usize, data: &[u8; PAYLOAD_SIZE]) {
+        let (update, acc_code) = data.split_at(SIZEOF_CONFIG);
+        let current = match self.slots[slot] {
+            Some(ref mut c) => c,
+            None => return,
+        };
+        if current.acc_code() != &acc_code[..ACC_CODE_SIZE]
+            || !current.ext_flags().contains(ExtendedFlags::ALLOW_UPDATE)
+            || crc16(update) != CRC_RESIDUAL_OK
+        {
+            return;
+        }
+
+        let masks = [
+            (45, ExtendedFlags::UPDATE_MASK.bits()),
+            (46, TicketFlags::UPDATE_MASK.bits()),
+            (47, ConfigFlags::UPDATE_MASK.bits()),
+        ];
+        for (offset, mask) in masks {
+            current.0[offset] = (current.0[offset] & !mask) | (update[offset] & mask);
+        }
+        current.0[ACC_CODE_OFFSET..ACC_CODE_OFFSET + ACC_CODE_SIZE]
+            .copy_from_slice(&update[ACC_CODE_OFFSET..ACC_CODE_OFFSET + ACC_CODE_SIZE]);
+        self.written()
+    }
+
+    fn swap_slots(&mut self, data: &[u8; PAYLOAD_SIZE]) {
+        let acc_code = &data[SIZEOF_CONFIG..SIZEOF_CONFIG + ACC_CODE_SIZE];
+        if self
+            .slots
+            .iter()
+            .flatten()
+            .any(|s| s.acc_code() != acc_code)
+        {
+            return;
+        }
+
+        self.slots.swap(0, 1);
+        self.written()
+    }
+
+    fn device_info(&mut self) {
+        if self.version < (4, 1, 0) {
+            return;
+        }
+
+        let mut tlvs = Vec::new();
+        let mut push = |tag: u8, value: &[u8]| {
+            tlvs.push(tag);
+            tlvs.push(value.len() as u8);
+            tlvs.extend_from_slice(value);
+        };
+        push(TAG_USB_SUPPORTED, &self.usb_supported.bits().to_be_bytes());
+        push(TAG_SERIAL, &self.serial.to_be_bytes());
+        push(TAG_USB_ENABLED, &self.usb_enabled.bits().to_be_bytes());
+        push(TAG_FORM_FACTOR, &[self.form_factor]);
+        push(
+            TAG_VERSION,
+            &[self.version.0, self.version.1, self.version.2],
+        );
+        push(
+            TAG_AUTO_EJECT_TIMEOUT,
+            &self.auto_eject_timeout.to_be_bytes(),
+        );
+        push(TAG_CHALRESP_TIMEOUT, &[self.challenge_response_timeout]);
+        push(TAG_DEVICE_FLAGS, &[self.device_flags]);
+        push(TAG_CONFIG_LOCK, &[self.lock_code.is_some() as u8]);
+
+        let mut data = vec![tlvs.len() as u8];
+        data.extend_from_slice(&tlvs);
+        self.respond(&data)
+    }
+
+    fn set_device_mode(&mut self, data: &[u8; PAYLOAD_SIZE]) {
+        if self.version < (3, 0, 0) {
+            return;
+        }
+        self.challenge_response_timeout = data[1];
+        self.auto_eject_timeout = u16::from_le_bytes([data[2], data[3]]);
+        self.written()
+    }
+
+    fn write_device_config(&mut self, data: &[u8; PAYLOAD_SIZE]) {
+        let len = data[0] as usize;
+        if self.version < (5, 0, 0) || len >= PAYLOAD_SIZE {
+            return;
+        }
+        let tlvs = match parse_tlvs(&data[1..len + 1]) {
+            Ok(tlvs) => tlvs,
+            Err(_) => return,
+        };
+
+        if let Some(ref lock_code) = self.lock_code {
+            if !tlvs
+                .iter()
+                .any(|&(tag, value)| tag == TAG_UNLOCK && value == lock_code)
+            {
+                return;
+            }
+        }
+
+        for (tag, value) in tlvs {
+            let int = value.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32);
+            match tag {
+                TAG_USB_ENABLED => {
+                    self.usb_enabled =
+                        Capabilities::from_bits_truncate(int as u16) & self.usb_supported
+                }
+                TAG_AUTO_EJECT_TIMEOUT => self.auto_eject_timeout = int as u16,
+                TAG_CHALRESP_TIMEOUT => self.challenge_response_timeout = int as u8,
+                TAG_DEVICE_FLAGS => self.device_flags = int as u8,
+                TAG_CONFIG_LOCK if value.len() == LOCK_CODE_SIZE => {
+                    let mut code = [0; LOCK_CODE_SIZE];
+                    code.copy_from_slice(value);
+                    self.lock_code = if code.iter().all(|&b| b == 0) {
+                        None
+                    } else {
+                        Some(code)
+                    };
+                }
+                _ => {}
+            }
+        }
+        self.written()
+    }
+
+    fn write_ndef(&mut self, slot: usize, data: &[u8; PAYLOAD_SIZE]) {
+        let (ndef, acc_code) = data.split_at(SIZEOF_NDEF);
+        if let Some(ref current) = self.slots[slot] {
+            if current.acc_code() != &acc_code[..ACC_CODE_SIZE] {
+                return;
+            }
+        }
+
+        let mut bytes = [0; SIZEOF_NDEF];
+        bytes.copy_from_slice(ndef);
+        self.ndef[slot] = Some(bytes);
+        self.written()
+    }
+
+    fn challenge_hmac(&mut self, slot: usize, data: &[u8; PAYLOAD_SIZE]) {
+        let config = match self.slots[slot] {
+            Some(ref c) if c.is_challenge_response(ConfigFlags::CHAL_HMAC) => c,
+            _ => return,
+        };
+
+        let mut len = PAYLOAD_SIZE;
+        if config.cfg_flags().contains(ConfigFlags::HMAC_LT64) {
+            let last = data[PAYLOAD_SIZE - 1];
+            while len > 0 && data[len - 1] == last {
+                len -= 1
+            }
+        }
+
+        let mut key = HmacKey([0; 20]);
+        key.0[..16].copy_from_slice(config.key());
+        key.0[16..].copy_from_slice(&config.uid()[..4]);
+        let hmac = hmac_sha1(&key, &data[..len]);
+        let touch = config.cfg_flags().contains(ConfigFlags::CHAL_BTN_TRIG);
+        self.respond(&hmac);
+        if touch {
+            self.wait_for_touch()
+        }
+    }
+
+    fn challenge_otp(&mut self, slot: usize, data: &[u8; PAYLOAD_SIZE]) {
+        let config = match self.slots[slot] {
+            Some(ref c) if c.is_challenge_response(ConfigFlags::CHAL_YUBICO) => c,
+            _ => return,
+        };
+        let touch = config.cfg_flags().contains(ConfigFlags::CHAL_BTN_TRIG);
+
+        self.session_counter = self.session_counter.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(1);
+
+        let mut block = [0; 16];
+        for (i, b) in block[..6].iter_mut().enumerate() {
+            *b = config.uid()[i] ^ data[i]
+        }
+        block[6..8].copy_from_slice(&self.use_counter.to_le_bytes());
+        block[8..11].copy_from_slice(&self.timestamp.to_le_bytes()[..3]);
+        block[11] = self.session_counter;
+        block[12..14].copy_from_slice(&rand::random::<u16>().to_le_bytes());
+        let crc = !crc16(&block[..14]);
+        block[14..].copy_from_slice(&crc.to_le_bytes());
+
+        let aes = Aes128::new(GenericArray::from_slice(config.key()));
+        let mut block = GenericArray::clone_from_slice(&block);
+        aes.encrypt_block(&mut block);
+        self.respond(&block);
+        if touch {
+            self.wait_for_touch()
+        }
+    }
+
+    /// Holds the queued response back until the key is touched.
+    fn wait_for_touch(&mut self) {
+        self.response_seq = None;
+        self.touch_wait = Some(TOUCH_POLLS);
+    }
+
+    /// Queues a response, followed by its CRC, for the host to read.
+    fn respond(&mut self, data: &[u8]) {
+        self.response.clear();
+        self.response.extend_from_slice(data);
+        let crc = !crc16(data);
+        self.response.extend_from_slice(&crc.to_le_bytes());
+        let padding = (7 - self.response.len() % 7) % 7;
+        self.response.resize(self.response.len() + padding, 0);
+        self.response_seq = Some(0);
+    }
+}
+
+impl Transport for Emulator {
+    fn devices(&mut self) -> Result<Vec<Device>, YubicoError> {
+        Ok(vec![self.device()])
+    }
+
+    fn open(&mut self, device: &Device) -> Result<(), YubicoError> {
+        let own = self.device();
+        if device.vendor_id != own.vendor_id
+            || device.product_id != own.product_id
+            || device.bus_number != own.bus_number
+            || device.address != own.address
+        {
+            return Err(YubicoError::DeviceNotFound);
+        }
+        self.opened = true;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), YubicoError> {
+        self.opened = false;
+        Ok(())
+    }
+
+    fn get_feature_report(&mut self, buf: &mut [u8; 8]) -> Result<usize, YubicoError> {
+        if !self.opened {
+            return Err(YubicoError::OpenDeviceError);
+        }
+
+        if let Some(polls) = self.touch_wait {
+            if polls > 0 {
+                self.touch_wait = Some(polls - 1);
+                buf.copy_from_slice(&self.status());
+                buf[7] =
+                    Flags::RESP_TIMEOUT_WAIT_FLAG.bits() | (self.challenge_response_timeout & 0x1f);
+                return Ok(8);
+            }
+            self.touch_wait = None;
+            if self.touch {
+                self.response_seq = Some(0);
+            } else {
+                self.response.clear();
+            }
+        }
+
+        match self.response_seq {
+            Some(seq) => {
+                let chunks = self.response.len() / 7;
+                let seq = seq % chunks;
+                buf[..7].copy_from_slice(&self.response[seq * 7..seq * 7 + 7]);
+                buf[7] = Flags::RESP_PENDING_FLAG.bits() | seq as u8;
+                self.response_seq = Some(seq + 1);
+            }
+            None => buf.copy_from_slice(&self.status()),
+        }
+        Ok(8)
+    }
+
+    fn set_feature_report(&mut self, packet: &[u8; 8]) -> Result<(), YubicoError> {
+        if !self.opened {
+            return Err(YubicoError::OpenDeviceError);
+        }
+
+        let flags = packet[7];
+        if flags & Flags::SLOT_WRITE_FLAG.bits() == 0 {
+            return Ok(());
+        }
+
+        let seq = flags & 0x1f;
+        if seq == RESET_SEQ {
+            self.response.clear();
+            self.response_seq = None;
+            self.touch_wait = None;
+            return Ok(());
+        }
+
+        let offset = seq as usize * 7;
+        if offset + 7 > FRAME_SIZE {
+            return Err(YubicoError::CanNotWriteToDevice);
+        }
+        if seq == 0 {
+            self.frame = [0; FRAME_SIZE];
+            self.response.clear();
+            self.response_seq = None;
+        }
+        self.frame[offset..offset + 7].copy_from_slice(&packet[..7]);
+
+        if offset + 7 == FRAME_SIZE {
+            self.process_frame()
+        }
+        Ok(())
+    }
+}
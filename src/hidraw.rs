@@ -0,0 +1,236 @@
+//! A transport through the Linux hidraw driver.
+//!
+//! The kernel already exposes the OTP interface as `/dev/hidrawN`, so
+//! feature reports can be exchanged without detaching any driver, with
+//! the permissions udev gives the node.
+
+use crate::transport::Transport;
+use crate::yubicoerror::YubicoError;
+use crate::{Device, VENDOR_ID};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+const SYSFS_HIDRAW: &str = "/sys/class/hidraw";
+
+/// The OTP application is the keyboard of the key: usage page
+/// "generic desktop", usage "keyboard".
+const USAGE_PAGE_GENERIC_DESKTOP: u32 = 0x01;
+const USAGE_KEYBOARD: u32 = 0x06;
+
+const IOC_WRITE: libc::c_ulong = 1;
+const IOC_READ: libc::c_ulong = 2;
+
+/// `_IOC(_IOC_WRITE | _IOC_READ, 'H', nr, len)`, as in `linux/hidraw.h`.
+fn hid_ioc(nr: libc::c_ulong, len: usize) -> libc::c_ulong {
+    ((IOC_WRITE | IOC_READ) << 30)
+        | ((len as libc::c_ulong) << 16)
+        | ((b'H' as libc::c_ulong) << 8)
+        | nr
+}
+
+fn hidiocsfeature(len: usize) -> libc::c_ulong {
+    hid_ioc(0x06, len)
+}
+
+fn hidiocgfeature(len: usize) -> libc::c_ulong {
+    hid_ioc(0x07, len)
+}
+
+/// A transport using the hidraw nodes of the OTP interface.
+#[derive(Debug, Default)]
+pub struct HidrawTransport {
+    file: Option<File>,
+}
+
+impl HidrawTransport {
+    pub fn new() -> Self {
+        HidrawTransport::default()
+    }
+
+    fn file(&self) -> Result<&File, YubicoError> {
+        self.file.as_ref().ok_or(YubicoError::OpenDeviceError)
+    }
+
+    /// Lists the OTP interfaces of the connected YubiKeys, along with
+    /// their device nodes.
+    fn nodes(&self) -> Result<Vec<(Device, PathBuf)>, YubicoError> {
+        let entries = match fs::read_dir(SYSFS_HIDRAW) {
+            Ok(entries) => entries,
+            // No hidraw device at all.
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut nodes = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if let Some(device) = otp_device(&entry.path()) {
+                nodes.push((device, Path::new("/dev").join(entry.file_name())));
+            }
+        }
+        Ok(nodes)
+    }
+}
+
+/// Reads the sysfs entry of a hidraw node, returning the YubiKey it
+/// belongs to if it is an OTP interface.
+fn otp_device(sysfs: &Path) -> Option<Device> {
+    // HID_ID=0003:00001050:00000407, the bus type being USB.
+    let uevent = fs::read_to_string(sysfs.join("device/uevent")).ok()?;
+    let id = uevent.lines().find_map(|l| l.strip_prefix("HID_ID="))?;
+    let mut fields = id.split(':');
+    if u16::from_str_radix(fields.next()?, 16).ok()? != 0x03 {
+        return None;
+    }
+    let vendor_id = u32::from_str_radix(fields.next()?, 16).ok()? as u16;
+    let product_id = u32::from_str_radix(fields.next()?, 16).ok()? as u16;
+    if vendor_id != VENDOR_ID {
+        return None;
+    }
+
+    let descriptor = fs::read(sysfs.join("device/report_descriptor")).ok()?;
+    if top_level_usage(&descriptor)? != (USAGE_PAGE_GENERIC_DESKTOP, USAGE_KEYBOARD) {
+        return None;
+    }
+
+    // The HID device sits below its USB interface, itself below the
+    // USB device.
+    let hid = fs::canonicalize(sysfs.join("device")).ok()?;
+    let usb = hid.parent()?.parent()?;
+    let read_number =
+        |name: &str| -> Option<u8> { fs::read_to_string(usb.join(name)).ok()?.trim().parse().ok() };
+
+    Some(Device {
+        product_id,
+        vendor_id,
+        bus_number: read_number("busnum")?,
+        address: read_number("devnum")?,
+        serial: None,
+        version: None,
+    })
+}
+
+/// Returns the usage page and usage of the first collection of a
+/// report descriptor.
+fn top_level_usage(mut descriptor: &[u8]) -> Option<(u32, u32)> {
+    let mut usage_page = None;
+    let mut usage = None;
+    while let Some(&prefix) = descriptor.first() {
+        let size = match prefix & 0x03 {
+            3 => 4,
+            size => size as usize,
+        };
+        let data = descriptor.get(1..1 + size)?;
+        let value = data.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u32);
+        match prefix & 0xfc {
+            0x04 => usage_page = Some(value),
+            0x08 => usage = Some(value),
+            _ => {}
+        }
+        if let (Some(page), Some(usage)) = (usage_page, usage) {
+            return Some((page, usage));
+        }
+        descriptor = &descriptor[1 + size..];
+    }
+    None
+}
+
+impl Transport for HidrawTransport {
+    fn devices(&mut self) -> Result<Vec<Device>, YubicoError> {
+        Ok(self
+            .nodes()?
+            .into_iter()
+            .map(|(device, _)| device)
+            .collect())
+    }
+
+    fn open(&mut self, device: &Device) -> Result<(), YubicoError> {
+        let (_, node) = self
+            .nodes()?
+            .into_iter()
+            .find(|(d, _)| {
+                d.vendor_id == device.vendor_id
+                    && d.product_id == device.product_id
+                    && d.bus_number == device.bus_number
+                    && d.address == device.address
+            })
+            .ok_or(YubicoError::DeviceNotFound)?;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(node)
+            .map_err(|_| YubicoError::OpenDeviceError)?;
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), YubicoError> {
+        self.file = None;
+        Ok(())
+    }
+
+    fn get_feature_report(&mut self, buf: &mut [u8; 8]) -> Result<usize, YubicoError> {
+        // The first byte is the report number, and the key has none.
+        let mut report = [0u8; 9];
+        let fd = self.file()?.as_raw_fd();
+        let len =
+            unsafe { libc::ioctl(fd, hidiocgfeature(report.len()) as _, report.as_mut_ptr()) };
+        if len < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        buf.copy_from_slice(&report[1..]);
+        Ok((len as usize).saturating_sub(1))
+    }
+
+    fn set_feature_report(&mut self, packet: &[u8; 8]) -> Result<(), YubicoError> {
+        let mut report = [0u8; 9];
+        report[1..].copy_from_slice(packet);
+        let fd = self.file()?.as_raw_fd();
+        let len = unsafe { libc::ioctl(fd, hidiocsfeature(report.len()) as _, report.as_ptr()) };
+        if len < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        if len as usize != report.len() {
+            return Err(YubicoError::CanNotWriteToDevice);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyboard_usage() {
+        // Usage Page (Generic Desktop), Usage (Keyboard), Collection...
+        let descriptor = [0x05, 0x01, 0x09, 0x06, 0xa1, 0x01, 0x05, 0x07, 0x19, 0xe0];
+        assert_eq!(
+            top_level_usage(&descriptor),
+            Some((USAGE_PAGE_GENERIC_DESKTOP, USAGE_KEYBOARD))
+        );
+    }
+
+    #[test]
+    fn fido_usage() {
+        // Usage Page (0xf1d0), two bytes little-endian, Usage (U2F HID).
+        let descriptor = [0x06, 0xd0, 0xf1, 0x09, 0x01, 0xa1, 0x01];
+        assert_eq!(top_level_usage(&descriptor), Some((0xf1d0, 0x01)));
+    }
+
+    #[test]
+    fn four_byte_items() {
+        let descriptor = [0x07, 0x01, 0x00, 0x00, 0x00, 0x0b, 0x06, 0x00, 0x01, 0x00];
+        assert_eq!(top_level_usage(&descriptor), Some((0x01, 0x10006)));
+    }
+
+    #[test]
+    fn truncated_descriptor() {
+        assert_eq!(top_level_usage(&[0x05, 0x01, 0x0a, 0x06]), None);
+        assert_eq!(top_level_usage(&[0x05, 0x01]), None);
+        assert_eq!(top_level_usage(&[]), None);
+    }
+}
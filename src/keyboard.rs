@@ -0,0 +1,290 @@
+//! Keyboard layouts, mapping characters to the HID usage codes the
+//! YubiKey sends when it types, and back.
+//!
+//! The key only knows about scan codes: the host keyboard layout
+//! decides which characters appear. Static passwords must therefore
+//! be encoded for the layout of the machine they are typed into, and
+//! text typed by the key (modhex OTPs for instance) can be recovered
+//! by mapping it back to scan codes on the host layout.
+
+use crate::yubicoerror::YubicoError;
+
+/// Set on a scan code to type it with the shift key held down.
+pub const SHIFT: u8 = 0x80;
+
+/// The characters the YubiKey types, in the order of the scan map:
+/// modhex in lower and upper case, digits, '!', tab and enter.
+const SCAN_MAP_CHARS: &str = "cbdefghijklnrtuvCBDEFGHIJKLNRTUV0123456789!\t\n";
+
+/// The size of the scan map.
+pub const SCAN_MAP_SIZE: usize = 45;
+
+const ENTER: u8 = 0x28;
+const TAB: u8 = 0x2b;
+const SPACE: u8 = 0x2c;
+
+/// Characters the layouts cannot type without AltGr or dead keys.
+const NONE: char = '\0';
+
+/// Scan codes of the keys described by each row of a layout: the
+/// letters, the digits, the punctuation keys, and the extra key of
+/// ISO keyboards.
+const KEYS: [u8; 49] = [
+    0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13,
+    0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x20, 0x21, 0x22, 0x23,
+    0x24, 0x25, 0x26, 0x27, 0x2d, 0x2e, 0x2f, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38,
+    0x64,
+];
+
+/// The characters typed by each of `KEYS`, without and with shift.
+struct Rows {
+    normal: &'static str,
+    shifted: &'static str,
+}
+
+const US: Rows = Rows {
+    normal: "abcdefghijklmnopqrstuvwxyz1234567890-=[]\\\0;'`,./\0",
+    shifted: "ABCDEFGHIJKLMNOPQRSTUVWXYZ!@#$%^&*()_+{}|\0:\"~<>?\0",
+};
+
+const UK: Rows = Rows {
+    normal: "abcdefghijklmnopqrstuvwxyz1234567890-=[]\0#;'`,./\\",
+    shifted: "ABCDEFGHIJKLMNOPQRSTUVWXYZ!\"£$%^&*()_+{}\0~:@¬<>?|",
+};
+
+const DE: Rows = Rows {
+    normal: "abcdefghijklmnopqrstuvwxzy1234567890ß\0ü+\0#öä\0,.-<",
+    shifted: "ABCDEFGHIJKLMNOPQRSTUVWXZY!\"§$%&/()=?\0Ü*\0'ÖÄ°;:_>",
+};
+
+const FR: Rows = Rows {
+    normal: "qbcdefghijkl,noparstuvzxyw&é\"'(-è_çà)=\0$\0*mù²;:!<",
+    shifted: "QBCDEFGHIJKL?NOPARSTUVZXYW1234567890°+\0£\0µM%\0./§>",
+};
+
+const DVORAK: Rows = Rows {
+    normal: "axje.uidchtnmbrl'poygk,qf;1234567890[]/=\\\0s-`wvz\0",
+    shifted: "AXJE>UIDCHTNMBRL\"POYGK<QF:!@#$%^&*(){}?+|\0S_~WVZ\0",
+};
+
+/// The host keyboard layout the YubiKey output is typed into.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyboardLayout {
+    Us,
+    Uk,
+    De,
+    Fr,
+    Dvorak,
+}
+
+impl KeyboardLayout {
+    fn rows(self) -> &'static Rows {
+        match self {
+            KeyboardLayout::Us => &US,
+            KeyboardLayout::Uk => &UK,
+            KeyboardLayout::De => &DE,
+            KeyboardLayout::Fr => &FR,
+            KeyboardLayout::Dvorak => &DVORAK,
+        }
+    }
+
+    /// Returns the scan code typing `c`, or `None` if the layout
+    /// cannot type it.
+    pub fn scan_code(self, c: char) -> Option<u8> {
+        match c {
+            NONE => return None,
+            '\n' => return Some(ENTER),
+            '\t' => return Some(TAB),
+            ' ' => return Some(SPACE),
+            _ => {}
+        }
+
+        let rows = self.rows();
+        if let Some(i) = rows.normal.chars().position(|ch| ch == c) {
+            return Some(KEYS[i]);
+        }
+        rows.shifted
+            .chars()
+            .position(|ch| ch == c)
+            .map(|i| SHIFT | KEYS[i])
+    }
+
+    /// Returns the character typed by a scan code, or `None` if the
+    /// key does not type anything on its own in this layout.
+    pub fn character(self, scan_code: u8) -> Option<char> {
+        match scan_code {
+            ENTER => return Some('\n'),
+            TAB => return Some('\t'),
+            SPACE => return Some(' '),
+            _ => {}
+        }
+
+        let i = KEYS.iter().position(|&k| k == scan_code & !SHIFT)?;
+        let rows = self.rows();
+        let row = if scan_code & SHIFT == 0 {
+            rows.normal
+        } else {
+            rows.shifted
+        };
+        row.chars().nth(i).filter(|&c| c != NONE)
+    }
+
+    /// Converts a string to scan codes.
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, YubicoError> {
+        text.chars()
+            .map(|c| {
+                self.scan_code(c)
+                    .ok_or(YubicoError::UnsupportedCharacter(c))
+            })
+            .collect()
+    }
+
+    /// Converts scan codes to a string, or returns `None` if one of
+    /// them does not type anything in this layout.
+    pub fn decode(self, scan_codes: &[u8]) -> Option<String> {
+        scan_codes
+            .iter()
+            .map(|&code| self.character(code))
+            .collect()
+    }
+
+    /// Converts text typed on this layout to the text the same keys
+    /// type on `layout`. For instance, a modhex OTP typed by the key
+    /// into a German host is recovered with
+    /// `KeyboardLayout::De.translate(otp, KeyboardLayout::Us)`.
+    pub fn translate(self, text: &str, layout: KeyboardLayout) -> Result<String, YubicoError> {
+        text.chars()
+            .map(|c| {
+                self.scan_code(c)
+                    .and_then(|code| layout.character(code))
+                    .ok_or(YubicoError::UnsupportedCharacter(c))
+            })
+            .collect()
+    }
+}
+
+/// The scan codes the YubiKey (firmware 3 and later) uses to type its
+/// output, replacing the built-in US map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScanMap([u8; SCAN_MAP_SIZE]);
+
+impl Default for ScanMap {
+    fn default() -> Self {
+        ScanMap::from_layout(KeyboardLayout::Us).unwrap()
+    }
+}
+
+impl ScanMap {
+    /// Builds the map typing the expected characters on a host using
+    /// `layout`.
+    pub fn from_layout(layout: KeyboardLayout) -> Result<Self, YubicoError> {
+        let mut map = [0; SCAN_MAP_SIZE];
+        for (code, c) in map.iter_mut().zip(SCAN_MAP_CHARS.chars()) {
+            *code = layout
+                .scan_code(c)
+                .ok_or(YubicoError::UnsupportedCharacter(c))?;
+        }
+        Ok(ScanMap(map))
+    }
+
+    pub fn from_bytes(map: &[u8; SCAN_MAP_SIZE]) -> Self {
+        ScanMap(*map)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; SCAN_MAP_SIZE] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUTS: [KeyboardLayout; 5] = [
+        KeyboardLayout::Us,
+        KeyboardLayout::Uk,
+        KeyboardLayout::De,
+        KeyboardLayout::Fr,
+        KeyboardLayout::Dvorak,
+    ];
+
+    #[test]
+    fn rows_cover_all_keys() {
+        for layout in LAYOUTS {
+            let rows = layout.rows();
+            assert_eq!(rows.normal.chars().count(), KEYS.len(), "{:?}", layout);
+            assert_eq!(rows.shifted.chars().count(), KEYS.len(), "{:?}", layout);
+        }
+    }
+
+    #[test]
+    fn characters_round_trip() {
+        for layout in LAYOUTS {
+            let rows = layout.rows();
+            for c in rows.normal.chars().chain(rows.shifted.chars()) {
+                if c == NONE {
+                    continue;
+                }
+                let code = layout.scan_code(c).unwrap();
+                assert_eq!(layout.character(code), Some(c), "{:?} {:?}", layout, c);
+            }
+        }
+    }
+
+    #[test]
+    fn scan_map_chars_are_typable() {
+        for layout in LAYOUTS {
+            let map = ScanMap::from_layout(layout).unwrap();
+            let text = layout.decode(map.as_bytes()).unwrap();
+            assert_eq!(text, SCAN_MAP_CHARS, "{:?}", layout);
+        }
+    }
+
+    #[test]
+    fn translate_between_layouts() {
+        assert_eq!(
+            KeyboardLayout::Us
+                .translate("qwerty", KeyboardLayout::Fr)
+                .unwrap(),
+            "azerty"
+        );
+        assert_eq!(
+            KeyboardLayout::Us
+                .translate("yz", KeyboardLayout::De)
+                .unwrap(),
+            "zy"
+        );
+        assert_eq!(
+            KeyboardLayout::Us
+                .translate("hello", KeyboardLayout::Dvorak)
+                .unwrap(),
+            "d.nnr"
+        );
+        assert_eq!(
+            KeyboardLayout::Us
+                .translate("@\"", KeyboardLayout::Uk)
+                .unwrap(),
+            "\"@"
+        );
+    }
+
+    #[test]
+    fn translate_round_trips() {
+        let text = "cbdefghijklnrtuvCBDEFGHIJKLNRTUV0123456789";
+        for from in LAYOUTS {
+            for to in LAYOUTS {
+                let typed = KeyboardLayout::Us.translate(text, from).unwrap();
+                let moved = from.translate(&typed, to).unwrap();
+                assert_eq!(to.translate(&moved, KeyboardLayout::Us).unwrap(), text);
+            }
+        }
+    }
+
+    #[test]
+    fn untypable_characters_are_rejected() {
+        match KeyboardLayout::Us.encode("pa€") {
+            Err(YubicoError::UnsupportedCharacter('€')) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -4,38 +4,65 @@ extern crate structure;
 #[macro_use]
 extern crate bitflags;
 
+pub mod ccid;
 pub mod config;
 pub mod configure;
+pub mod deviceinfo;
+#[cfg(feature = "emulator")]
+pub mod emulator;
+#[cfg(all(feature = "hidraw", target_os = "linux"))]
+pub mod hidraw;
 pub mod hmacmode;
+pub mod keyboard;
 mod manager;
+pub mod ndef;
 pub mod otpmode;
 pub mod sec;
+pub mod session;
+pub mod status;
+pub mod transport;
 pub mod yubicoerror;
 
-use aes::cipher::generic_array::GenericArray;
-use config::Command;
-use config::{Config, Slot};
-use configure::DeviceModeConfig;
+use config::{Config, DeviceMode, Slot};
+use configure::{DeviceModeConfig, UpdateConfig};
+use deviceinfo::{DeviceConfigUpdate, DeviceInfo};
 use hmacmode::Hmac;
-use manager::{Flags, Frame};
+use keyboard::ScanMap;
+use manager::Deadline;
+use ndef::NdefPayload;
 use otpmode::Aes128Block;
-use rusb::{Context, UsbContext};
-use sec::{crc16, CRC_RESIDUAL_OK};
+use session::YubikeySession;
+use status::{DeviceStatus, SlotStatus};
+use std::time::Duration;
+use transport::{Transport, UsbTransport};
 use yubicoerror::YubicoError;
 
 const VENDOR_ID: u16 = 0x1050;
+/// How long a device may take to report its version and serial while
+/// listing devices. Keys with a hidden serial never answer.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// The `Result` type used in this crate.
 type Result<T> = ::std::result::Result<T, YubicoError>;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Device {
     pub product_id: u16,
     pub vendor_id: u16,
+    /// The USB bus the device is attached to.
+    pub bus_number: u8,
+    /// The address of the device on its bus.
+    pub address: u8,
+    /// The serial number, filled in by `Yubico::list_devices` when
+    /// the key makes it visible over the API.
+    pub serial: Option<u32>,
+    /// The firmware version, filled in by `Yubico::list_devices`.
+    pub version: Option<(u8, u8, u8)>,
 }
 
-pub struct Yubico {
-    context: Context,
+pub struct Yubico<T: Transport = UsbTransport> {
+    transport: T,
+    status: Option<DeviceStatus>,
 }
 
 impl Default for Yubico {
@@ -45,170 +72,297 @@ impl Default for Yubico {
 }
 
 impl Yubico {
-    /// Creates a new Yubico instance.
+    /// Creates a new Yubico instance, talking to the device through libusb.
     pub fn new() -> Self {
-        Yubico {
-            context: Context::new().unwrap(),
-        }
+        Yubico::with_transport(UsbTransport::new().unwrap())
     }
+}
 
-    pub fn find_yubikey(&mut self) -> Result<Device> {
-        for device in self.context.devices().unwrap().iter() {
-            let descr = device.device_descriptor().unwrap();
-            if descr.vendor_id() == VENDOR_ID {
-                let device = Device {
-                    product_id: descr.product_id(),
-                    vendor_id: descr.vendor_id(),
-                };
-                return Ok(device);
-            }
+impl<T: Transport> Yubico<T> {
+    /// Creates a new Yubico instance on top of a custom transport.
+    pub fn with_transport(transport: T) -> Self {
+        Yubico {
+            transport,
+            status: None,
         }
+    }
 
-        Err(YubicoError::DeviceNotFound)
+    /// Returns the underlying transport.
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
     }
 
-    pub fn write_config(
-        &mut self,
-        conf: Config,
-        device_config: &mut DeviceModeConfig,
-    ) -> Result<()> {
-        let d = device_config.to_frame(conf.command);
-        let mut buf = [0; 8];
+    /// Returns the last status block read from the device, if any.
+    pub fn status(&self) -> Option<DeviceStatus> {
+        self.status
+    }
 
-        match manager::open_device(&self.context, conf.vendor_id, conf.product_id) {
-            Ok((handle, interfaces)) => {
-                manager::wait(&handle, |f| !f.contains(Flags::SLOT_WRITE_FLAG), &mut buf)?;
+    /// Reads the status block of the device: firmware version,
+    /// programming sequence and touch level.
+    pub fn read_status(&mut self, conf: Config) -> Result<DeviceStatus> {
+        self.with_session(conf, |session, conf| session.read_status(conf))
+    }
 
-                // TODO: Should check version number.
+    /// Reads whether `slot` is configured, and whether it is triggered
+    /// by touch.
+    pub fn slot_status(&mut self, conf: Config, slot: Slot) -> Result<SlotStatus> {
+        self.with_session(conf, |session, conf| session.slot_status(conf, slot))
+    }
 
-                manager::write_frame(&handle, &d)?;
-                manager::wait(&handle, |f| !f.contains(Flags::SLOT_WRITE_FLAG), &mut buf)?;
-                manager::close_device(handle, interfaces)?;
+    pub fn find_yubikey(&mut self) -> Result<Device> {
+        self.transport
+            .devices()?
+            .into_iter()
+            .next()
+            .ok_or(YubicoError::DeviceNotFound)
+    }
 
-                Ok(())
+    /// Lists all connected YubiKeys, along with their firmware version
+    /// and serial number. Keys that can not be queried (busy, or with
+    /// the OTP application disabled) are listed without them.
+    pub fn list_devices(&mut self) -> Result<Vec<Device>> {
+        let mut devices = self.transport.devices()?;
+        for device in devices.iter_mut() {
+            let deadline = Deadline::new(Some(PROBE_TIMEOUT), None);
+            if let Ok((version, serial)) = self.probe(device, &deadline) {
+                device.version = Some(version);
+                device.serial = serial;
             }
-            Err(error) => Err(error),
         }
+        Ok(devices)
     }
 
-    pub fn read_serial_number(&mut self, conf: Config) -> Result<u32> {
-        match manager::open_device(&self.context, conf.vendor_id, conf.product_id) {
-            Ok((handle, interfaces)) => {
-                let challenge = [0; 64];
-                let command = Command::DeviceSerial;
-
-                let d = Frame::new(challenge, command); // FixMe: do not need a challange
-                let mut buf = [0; 8];
-                manager::wait(
-                    &handle,
-                    |f| !f.contains(manager::Flags::SLOT_WRITE_FLAG),
-                    &mut buf,
-                )?;
-
-                manager::write_frame(&handle, &d)?;
-
-                // Read the response.
-                let mut response = [0; 36];
-                manager::read_response(&handle, &mut response)?;
-                manager::close_device(handle, interfaces)?;
-
-                // Check response.
-                if crc16(&response[..6]) != CRC_RESIDUAL_OK {
-                    return Err(YubicoError::WrongCRC);
-                }
+    /// Opens `device` just long enough to read its version and serial.
+    fn probe(
+        &mut self,
+        device: &Device,
+        deadline: &Deadline,
+    ) -> Result<((u8, u8, u8), Option<u32>)> {
+        self.transport.open(device)?;
+        let info = self.read_version_and_serial(deadline);
+        let closed = self.transport.close();
+
+        let info = info?;
+        closed?;
+        Ok(info)
+    }
 
-                let serial = structure!("2I").unpack(&response[..8])?;
+    fn read_version_and_serial(
+        &mut self,
+        deadline: &Deadline,
+    ) -> Result<((u8, u8, u8), Option<u32>)> {
+        let status = manager::read_status(&mut self.transport, deadline)?;
+        // The serial number can only be read from YubiKey 2.2 onwards,
+        // and only if it is visible over the API.
+        let serial = if status.version >= (2, 2, 0) {
+            match manager::read_serial(&mut self.transport, deadline) {
+                Ok(serial) => Some(serial),
+                Err(YubicoError::Timeout) => None,
+                Err(error) => return Err(error),
+            }
+        } else {
+            None
+        };
+        Ok((status.version, serial))
+    }
 
-                Ok(serial.0)
+    /// Finds the device selected by `conf`: by bus and address if set,
+    /// else by serial number if set, else by product id.
+    fn select_device(&mut self, conf: &Config) -> Result<Device> {
+        let devices = self.transport.devices()?;
+        let mut candidates = devices
+            .into_iter()
+            .filter(|d| d.vendor_id == conf.vendor_id);
+
+        if let Some((bus_number, address)) = conf.bus_address {
+            candidates
+                .find(|d| d.bus_number == bus_number && d.address == address)
+                .ok_or(YubicoError::DeviceNotFound)
+        } else if let Some(serial) = conf.serial {
+            for device in candidates {
+                let deadline = Deadline::new(Some(PROBE_TIMEOUT), conf.cancellation.clone());
+                // A key that can not be queried is not the one we are
+                // looking for, but should not hide the others.
+                match self.probe(&device, &deadline) {
+                    Ok((_, Some(s))) if s == serial => return Ok(device),
+                    Err(YubicoError::Cancelled) => return Err(YubicoError::Cancelled),
+                    _ => {}
+                }
             }
-            Err(error) => Err(error),
+            Err(YubicoError::DeviceNotFound)
+        } else {
+            candidates
+                .find(|d| d.product_id == conf.product_id)
+                .ok_or(YubicoError::DeviceNotFound)
         }
     }
 
-    pub fn challenge_response_hmac(&mut self, chall: &[u8], conf: Config) -> Result<Hmac> {
-        let mut hmac = Hmac([0; 20]);
+    /// Opens `device` for a series of commands. The device stays open
+    /// until the session is closed or dropped.
+    pub fn open(&mut self, device: &Device) -> Result<YubikeySession<'_, T>> {
+        self.transport.open(device)?;
+        Ok(YubikeySession::new(&mut self.transport, &mut self.status))
+    }
 
-        match manager::open_device(&self.context, conf.vendor_id, conf.product_id) {
-            Ok((handle, interfaces)) => {
-                let mut challenge = [0; 64];
+    /// Runs `command` in a session on the device selected by `conf`.
+    fn with_session<R, F>(&mut self, conf: Config, command: F) -> Result<R>
+    where
+        F: FnOnce(&mut YubikeySession<'_, T>, Config) -> Result<R>,
+    {
+        let device = self.select_device(&conf)?;
+        let mut session = self.open(&device)?;
+        let result = command(&mut session, conf);
+        let closed = session.close();
+
+        let result = result?;
+        closed?;
+        Ok(result)
+    }
 
-                if conf.variable && chall.last() == Some(&0) {
-                    challenge = [0xff; 64];
-                }
+    pub fn write_config(
+        &mut self,
+        conf: Config,
+        device_config: &mut DeviceModeConfig,
+    ) -> Result<()> {
+        self.write_config_with_access_code(conf, device_config, None)
+    }
 
-                let mut command = Command::ChallengeHmac1;
-                if let Slot::Slot2 = conf.slot {
-                    command = Command::ChallengeHmac2;
-                }
+    /// Writes a configuration to a slot protected by `access_code`.
+    pub fn write_config_with_access_code(
+        &mut self,
+        conf: Config,
+        device_config: &mut DeviceModeConfig,
+        access_code: Option<&[u8; 6]>,
+    ) -> Result<()> {
+        self.with_session(conf, |session, conf| {
+            session.write_config_with_access_code(conf, device_config, access_code)
+        })
+    }
 
-                challenge[..chall.len()].copy_from_slice(chall);
-                let d = Frame::new(challenge, command);
-                let mut buf = [0; 8];
-                manager::wait(
-                    &handle,
-                    |f| !f.contains(manager::Flags::SLOT_WRITE_FLAG),
-                    &mut buf,
-                )?;
-
-                manager::write_frame(&handle, &d)?;
-
-                // Read the response.
-                let mut response = [0; 36];
-                manager::read_response(&handle, &mut response)?;
-                manager::close_device(handle, interfaces)?;
-
-                // Check response.
-                if crc16(&response[..22]) != CRC_RESIDUAL_OK {
-                    return Err(YubicoError::WrongCRC);
-                }
+    /// Swaps the configurations of slot 1 and slot 2. `access_code` is
+    /// the current access code of the slots, if they are protected.
+    pub fn swap_slots(&mut self, conf: Config, access_code: Option<&[u8; 6]>) -> Result<()> {
+        self.with_session(conf, |session, conf| session.swap_slots(conf, access_code))
+    }
 
-                hmac.0.clone_from_slice(&response[..20]);
+    /// Changes the output settings of a configured slot, keeping its
+    /// secrets. Every updatable flag is replaced by those of `update`.
+    /// `access_code` is the current access code of the slot, if it is
+    /// protected.
+    pub fn update_slot(
+        &mut self,
+        conf: Config,
+        slot: Slot,
+        update: &UpdateConfig,
+        access_code: Option<&[u8; 6]>,
+    ) -> Result<()> {
+        self.change_access_code(conf, slot, update, access_code, access_code)
+    }
 
-                Ok(hmac)
-            }
-            Err(error) => Err(error),
-        }
+    /// Sets, changes or removes the access code of a configured slot,
+    /// while applying `update` to it: every updatable flag is replaced,
+    /// so pass the flags the slot should keep (`UpdateConfig::default()`
+    /// keeps it updatable, with a visible serial). `access_code` is the
+    /// current access code of the slot, and `new_access_code` the one it
+    /// is protected by afterwards (`None` to remove the protection).
+    pub fn change_access_code(
+        &mut self,
+        conf: Config,
+        slot: Slot,
+        update: &UpdateConfig,
+        access_code: Option<&[u8; 6]>,
+        new_access_code: Option<&[u8; 6]>,
+    ) -> Result<()> {
+        self.with_session(conf, |session, conf| {
+            session.change_access_code(conf, slot, update, access_code, new_access_code)
+        })
     }
 
-    pub fn challenge_response_otp(&mut self, chall: &[u8], conf: Config) -> Result<Aes128Block> {
-        let mut block = Aes128Block {
-            block: GenericArray::clone_from_slice(&[0; 16]),
-        };
+    /// Erases the configuration of a slot. `access_code` is the current
+    /// access code of the slot, if it is protected.
+    pub fn delete_slot(
+        &mut self,
+        conf: Config,
+        slot: Slot,
+        access_code: Option<&[u8; 6]>,
+    ) -> Result<()> {
+        self.with_session(conf, |session, conf| {
+            session.delete_slot(conf, slot, access_code)
+        })
+    }
 
-        match manager::open_device(&self.context, conf.vendor_id, conf.product_id) {
-            Ok((handle, interfaces)) => {
-                let mut challenge = [0; 64];
-                //(&mut challenge[..6]).copy_from_slice(chall);
+    /// Selects the USB interfaces exposed by a YubiKey NEO or YubiKey
+    /// 4, the time the key waits for a touch in challenge-response mode
+    /// and, in CCID mode, the time after which the smart card is
+    /// ejected (0 disables ejecting). Times are in seconds. The key has
+    /// to be re-inserted for the new mode to apply.
+    pub fn set_device_mode(
+        &mut self,
+        conf: Config,
+        mode: DeviceMode,
+        cr_timeout: u8,
+        auto_eject_time: u16,
+    ) -> Result<()> {
+        self.with_session(conf, |session, conf| {
+            session.set_device_mode(conf, mode, cr_timeout, auto_eject_time)
+        })
+    }
 
-                let mut command = Command::ChallengeOtp1;
-                if let Slot::Slot2 = conf.slot {
-                    command = Command::ChallengeOtp2;
-                }
+    /// Reads the device information of a YubiKey 4.1 or later:
+    /// supported and enabled applications, form factor, serial...
+    pub fn read_device_info(&mut self, conf: Config) -> Result<DeviceInfo> {
+        self.with_session(conf, |session, conf| session.read_device_info(conf))
+    }
 
-                challenge[..chall.len()].copy_from_slice(chall);
-                let d = Frame::new(challenge, command);
-                let mut buf = [0; 8];
-
-                let mut response = [0; 36];
-                manager::wait(
-                    &handle,
-                    |f| !f.contains(manager::Flags::SLOT_WRITE_FLAG),
-                    &mut buf,
-                )?;
-                manager::write_frame(&handle, &d)?;
-                manager::read_response(&handle, &mut response)?;
-                manager::close_device(handle, interfaces)?;
-
-                // Check response.
-                if crc16(&response[..18]) != CRC_RESIDUAL_OK {
-                    return Err(YubicoError::WrongCRC);
-                }
+    /// Writes the device configuration of a YubiKey 5: enabled USB and
+    /// NFC applications, timeouts, device flags and lock code. Unless
+    /// the update reboots the key, the changes apply once it is
+    /// re-inserted.
+    ///
+    /// A rebooting update can not be verified: the key drops off the
+    /// bus before it can be asked whether it accepted the write, so
+    /// `Ok` only means that the update was sent. Read the device info
+    /// once the key is back to check it.
+    pub fn write_device_config(&mut self, conf: Config, update: &DeviceConfigUpdate) -> Result<()> {
+        self.with_session(conf, |session, conf| {
+            session.write_device_config(conf, update)
+        })
+    }
 
-                block.block.copy_from_slice(&response[..16]);
+    /// Programs the NDEF record sent over NFC when `slot` is
+    /// triggered. `access_code` is the current access code of the
+    /// slot, if it is protected.
+    pub fn write_ndef(
+        &mut self,
+        conf: Config,
+        slot: Slot,
+        payload: &NdefPayload,
+        access_code: Option<&[u8; 6]>,
+    ) -> Result<()> {
+        self.with_session(conf, |session, conf| {
+            session.write_ndef(conf, slot, payload, access_code)
+        })
+    }
 
-                Ok(block)
-            }
-            Err(error) => Err(error),
-        }
+    /// Replaces the scan codes the key types its output with, for hosts
+    /// whose keyboard layout mangles the default output.
+    pub fn write_scan_map(&mut self, conf: Config, scan_map: &ScanMap) -> Result<()> {
+        self.with_session(conf, |session, conf| session.write_scan_map(conf, scan_map))
+    }
+
+    pub fn read_serial_number(&mut self, conf: Config) -> Result<u32> {
+        self.with_session(conf, |session, conf| session.read_serial_number(conf))
+    }
+
+    pub fn challenge_response_hmac(&mut self, chall: &[u8], conf: Config) -> Result<Hmac> {
+        self.with_session(conf, |session, conf| {
+            session.challenge_response_hmac(chall, conf)
+        })
+    }
+
+    pub fn challenge_response_otp(&mut self, chall: &[u8], conf: Config) -> Result<Aes128Block> {
+        self.with_session(conf, |session, conf| {
+            session.challenge_response_otp(chall, conf)
+        })
     }
 }
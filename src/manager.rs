@@ -1,130 +1,193 @@
-use crate::config::Command;
-use crate::sec::crc16;
+use crate::config::{CancellationToken, Command, Config, TouchCallback, TouchEvent};
+use crate::sec::{crc16, CRC_RESIDUAL_OK};
+use crate::status::{DeviceStatus, CONFIG1_VALID, CONFIG2_VALID};
+use crate::transport::Transport;
 use crate::yubicoerror::YubicoError;
-use rusb::{request_type, Context, DeviceHandle, Direction, Recipient, RequestType, UsbContext};
-use std::time::Duration;
+use std::convert::TryInto;
+use std::time::{Duration, Instant};
 use std::{slice, thread};
 
 const DATA_SIZE: usize = 64;
-const HID_GET_REPORT: u8 = 0x01;
-const HID_SET_REPORT: u8 = 0x09;
-const REPORT_TYPE_FEATURE: u16 = 0x03;
 
 bitflags! {
     pub struct Flags: u8 {
         const SLOT_WRITE_FLAG = 0x80;
         const RESP_PENDING_FLAG = 0x40;
+        const RESP_TIMEOUT_WAIT_FLAG = 0x20;
     }
 }
 
-pub fn open_device(
-    context: &Context,
-    vid: u16,
-    pid: u16,
-) -> Result<(DeviceHandle<Context>, Vec<u8>), YubicoError> {
-    let devices = match context.devices() {
-        Ok(device) => device,
-        Err(_) => {
-            return Err(YubicoError::DeviceNotFound);
-        }
-    };
+const RESET_PACKET: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 0x8f];
 
-    for device in devices.iter() {
-        let device_desc = match device.device_descriptor() {
-            Ok(device) => device,
-            Err(_) => {
-                return Err(YubicoError::DeviceNotFound);
-            }
-        };
+/// Bounds the time an operation may spend waiting for the device.
+#[derive(Clone, Debug, Default)]
+pub struct Deadline {
+    expires: Option<Instant>,
+    cancellation: Option<CancellationToken>,
+}
 
-        if device_desc.vendor_id() == vid && device_desc.product_id() == pid {
-            match device.open() {
-                Ok(mut handle) => {
-                    let config = match device.config_descriptor(0) {
-                        Ok(c) => c,
-                        Err(_) => continue,
-                    };
-
-                    let mut interfaces = Vec::new();
-                    for interface in config.interfaces() {
-                        for usb_int in interface.descriptors() {
-                            match handle.kernel_driver_active(usb_int.interface_number()) {
-                                Ok(true) => {
-                                    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
-                                    handle.detach_kernel_driver(usb_int.interface_number())?;
-                                }
-                                _ => continue,
-                            };
-
-                            if handle.active_configuration()? != config.number() {
-                                handle.set_active_configuration(config.number())?;
-                            }
-                            #[cfg(not(any(target_os = "macos", target_os = "windows")))]
-                            handle.claim_interface(usb_int.interface_number())?;
-                            #[cfg(not(any(target_os = "macos", target_os = "windows")))]
-                            interfaces.push(usb_int.interface_number());
-                        }
-                    }
-
-                    return Ok((handle, interfaces));
-                }
-                Err(_) => {
-                    return Err(YubicoError::OpenDeviceError);
-                }
-            }
+impl Deadline {
+    pub fn new(timeout: Option<Duration>, cancellation: Option<CancellationToken>) -> Self {
+        Deadline {
+            expires: timeout.map(|timeout| Instant::now() + timeout),
+            cancellation,
         }
     }
 
-    Err(YubicoError::DeviceNotFound)
-}
-
-#[cfg(any(target_os = "macos", target_os = "windows"))]
-pub fn close_device(
-    _handle: DeviceHandle<Context>,
-    _interfaces: Vec<u8>,
-) -> Result<(), YubicoError> {
-    Ok(())
-}
+    /// The deadline of an operation run with `conf`, starting now.
+    pub fn from_config(conf: &Config) -> Self {
+        Deadline::new(conf.timeout, conf.cancellation.clone())
+    }
 
-#[cfg(not(any(target_os = "macos", target_os = "windows")))]
-pub fn close_device(
-    mut handle: DeviceHandle<Context>,
-    interfaces: Vec<u8>,
-) -> Result<(), YubicoError> {
-    for interface in interfaces {
-        handle.release_interface(interface)?;
-        handle.attach_kernel_driver(interface)?;
+    fn check(&self) -> Result<(), YubicoError> {
+        if let Some(ref cancellation) = self.cancellation {
+            if cancellation.is_cancelled() {
+                return Err(YubicoError::Cancelled);
+            }
+        }
+        match self.expires {
+            Some(expires) if Instant::now() >= expires => Err(YubicoError::Timeout),
+            _ => Ok(()),
+        }
     }
-    Ok(())
 }
 
-pub fn wait<F: Fn(Flags) -> bool>(
-    handle: &DeviceHandle<Context>,
-    f: F,
+/// Polls the status until `f` accepts its flags. If the deadline
+/// passes first, the pending operation is aborted with a reset packet,
+/// so that the key does not answer it later.
+pub fn wait<T: Transport, F: FnMut(Flags) -> bool>(
+    transport: &mut T,
+    mut f: F,
     buf: &mut [u8],
+    deadline: &Deadline,
 ) -> Result<(), YubicoError> {
     loop {
-        read(handle, buf)?;
+        read(transport, buf)?;
         let flags = Flags::from_bits_truncate(buf[7]);
-        if flags.contains(Flags::SLOT_WRITE_FLAG) || flags.is_empty() {
-            // Should store the version
-        }
-
         if f(flags) {
             return Ok(());
         }
+        if let Err(error) = deadline.check() {
+            raw_write(transport, &RESET_PACKET)?;
+            return Err(error);
+        }
         thread::sleep(Duration::new(0, 1000000));
     }
 }
 
-pub fn read(handle: &DeviceHandle<Context>, buf: &mut [u8]) -> Result<usize, YubicoError> {
+/// Waits for any pending write, then reads the status block.
+pub fn read_status<T: Transport>(
+    transport: &mut T,
+    deadline: &Deadline,
+) -> Result<DeviceStatus, YubicoError> {
+    let mut buf = [0; 8];
+    wait(
+        transport,
+        |f| !f.contains(Flags::SLOT_WRITE_FLAG),
+        &mut buf,
+        deadline,
+    )?;
+    Ok(DeviceStatus::from_report(&buf))
+}
+
+/// Writes a configuration frame and checks that the device accepted it.
+///
+/// The programming sequence is incremented by each successful write,
+/// so a write that leaves it unchanged has been rejected (wrong access
+/// code, protected slot...). The sequence stays at 0 while no slot is
+/// configured though, whatever the write, so it proves nothing on a
+/// blank key.
+pub fn write_config<T: Transport>(
+    transport: &mut T,
+    frame: &Frame,
+    deadline: &Deadline,
+) -> Result<DeviceStatus, YubicoError> {
+    let before = read_status(transport, deadline)?;
+    write_frame(transport, frame, deadline)?;
+    let after = read_status(transport, deadline)?;
+
+    let blank = after.pgm_seq == 0 && after.touch_level & (CONFIG1_VALID | CONFIG2_VALID) == 0;
+    if after.pgm_seq != before.pgm_seq || blank {
+        Ok(after)
+    } else {
+        Err(YubicoError::ConfigNotWritten)
+    }
+}
+
+/// Reads the serial number of the device.
+pub fn read_serial<T: Transport>(
+    transport: &mut T,
+    deadline: &Deadline,
+) -> Result<u32, YubicoError> {
+    let d = Frame::new([0; DATA_SIZE], Command::DeviceSerial);
+    let mut buf = [0; 8];
+    wait(
+        transport,
+        |f| !f.contains(Flags::SLOT_WRITE_FLAG),
+        &mut buf,
+        deadline,
+    )?;
+
+    write_frame(transport, &d, deadline)?;
+
+    // Read the response.
+    let mut response = [0; 36];
+    read_response(transport, &mut response, deadline, None)?;
+
+    // Check response.
+    if crc16(&response[..6]) != CRC_RESIDUAL_OK {
+        return Err(YubicoError::WrongCRC);
+    }
+
+    let serial = structure!("2I").unpack(&response[..8])?;
+
+    Ok(serial.0)
+}
+
+/// Reads one page of the device information TLVs.
+pub fn read_device_info_page<T: Transport>(
+    transport: &mut T,
+    page: u8,
+    deadline: &Deadline,
+) -> Result<Vec<u8>, YubicoError> {
+    let mut payload = [0; DATA_SIZE];
+    payload[0] = page;
+    let d = Frame::new(payload, Command::DeviceInfo);
+    let mut buf = [0; 8];
+    wait(
+        transport,
+        |f| !f.contains(Flags::SLOT_WRITE_FLAG),
+        &mut buf,
+        deadline,
+    )?;
+
+    write_frame(transport, &d, deadline)?;
+
+    // Read the response: a length byte, the TLVs and the CRC.
+    let mut response = [0; 80];
+    read_response(transport, &mut response, deadline, None)?;
+
+    let len = response[0] as usize;
+    if len + 3 > DATA_SIZE + 2 {
+        return Err(YubicoError::InvalidResponse);
+    }
+    if crc16(&response[..len + 3]) != CRC_RESIDUAL_OK {
+        return Err(YubicoError::WrongCRC);
+    }
+
+    Ok(response[1..len + 1].to_vec())
+}
+
+pub fn read<T: Transport>(transport: &mut T, buf: &mut [u8]) -> Result<usize, YubicoError> {
     assert_eq!(buf.len(), 8);
-    let reqtype = request_type(Direction::In, RequestType::Class, Recipient::Interface);
-    let value = REPORT_TYPE_FEATURE << 8;
-    Ok(handle.read_control(reqtype, HID_GET_REPORT, value, 0, buf, Duration::new(2, 0))?)
+    transport.get_feature_report(buf.try_into().unwrap())
 }
 
-pub fn write_frame(handle: &DeviceHandle<Context>, frame: &Frame) -> Result<(), YubicoError> {
+pub fn write_frame<T: Transport>(
+    transport: &mut T,
+    frame: &Frame,
+    deadline: &Deadline,
+) -> Result<(), YubicoError> {
     let mut data = unsafe { slice::from_raw_parts(frame as *const Frame as *const u8, 70) };
 
     let mut seq = 0;
@@ -137,8 +200,13 @@ pub fn write_frame(handle: &DeviceHandle<Context>, frame: &Frame) -> Result<(),
             packet[..7].copy_from_slice(a);
 
             packet[7] = Flags::SLOT_WRITE_FLAG.bits() + seq;
-            wait(handle, |x| !x.contains(Flags::SLOT_WRITE_FLAG), &mut buf)?;
-            raw_write(handle, &packet)?
+            wait(
+                transport,
+                |x| !x.contains(Flags::SLOT_WRITE_FLAG),
+                &mut buf,
+                deadline,
+            )?;
+            raw_write(transport, &packet)?
         }
         data = b;
         seq += 1
@@ -146,45 +214,78 @@ pub fn write_frame(handle: &DeviceHandle<Context>, frame: &Frame) -> Result<(),
     Ok(())
 }
 
-pub fn raw_write(handle: &DeviceHandle<Context>, packet: &[u8]) -> Result<(), YubicoError> {
-    let reqtype = request_type(Direction::Out, RequestType::Class, Recipient::Interface);
-    let value = REPORT_TYPE_FEATURE << 8;
-    if handle.write_control(
-        reqtype,
-        HID_SET_REPORT,
-        value,
-        0,
-        packet,
-        Duration::new(2, 0),
-    )? != 8
-    {
-        Err(YubicoError::CanNotWriteToDevice)
-    } else {
-        Ok(())
-    }
+pub fn raw_write<T: Transport>(transport: &mut T, packet: &[u8; 8]) -> Result<(), YubicoError> {
+    transport.set_feature_report(packet)
 }
 
 /// Reset the write state after a read.
-pub fn write_reset(handle: &DeviceHandle<Context>) -> Result<(), YubicoError> {
-    raw_write(handle, &[0, 0, 0, 0, 0, 0, 0, 0x8f])?;
+pub fn write_reset<T: Transport>(
+    transport: &mut T,
+    deadline: &Deadline,
+) -> Result<(), YubicoError> {
+    raw_write(transport, &RESET_PACKET)?;
     let mut buf = [0; 8];
-    wait(handle, |x| !x.contains(Flags::SLOT_WRITE_FLAG), &mut buf)?;
+    wait(
+        transport,
+        |x| !x.contains(Flags::SLOT_WRITE_FLAG),
+        &mut buf,
+        deadline,
+    )?;
     Ok(())
 }
 
-pub fn read_response(
-    handle: &DeviceHandle<Context>,
+pub fn read_response<T: Transport>(
+    transport: &mut T,
     response: &mut [u8],
+    deadline: &Deadline,
+    touch: Option<&TouchCallback>,
 ) -> Result<usize, YubicoError> {
-    let mut r0 = 0;
-    wait(
-        handle,
-        |f| f.contains(Flags::RESP_PENDING_FLAG),
+    let notify = |event| {
+        if let Some(callback) = touch {
+            callback.call(event)
+        }
+    };
+
+    // A slot triggered by touch raises the "waiting for touch" flag
+    // until it is touched, or gives up and goes back to idle.
+    let mut touch_pending = false;
+    let result = wait(
+        transport,
+        |f| {
+            if f.contains(Flags::RESP_TIMEOUT_WAIT_FLAG) {
+                if !touch_pending {
+                    touch_pending = true;
+                    notify(TouchEvent::Waiting)
+                }
+                false
+            } else {
+                f.contains(Flags::RESP_PENDING_FLAG)
+                    || (touch_pending && !f.contains(Flags::SLOT_WRITE_FLAG))
+            }
+        },
         &mut response[..8],
-    )?;
-    r0 += 7;
-    loop {
-        if read(handle, &mut response[r0..r0 + 8])? < 8 {
+        deadline,
+    );
+    if let Err(error) = result {
+        if touch_pending {
+            notify(match error {
+                YubicoError::Cancelled => TouchEvent::Cancelled,
+                _ => TouchEvent::TimedOut,
+            })
+        }
+        return Err(error);
+    }
+    if !Flags::from_bits_truncate(response[7]).contains(Flags::RESP_PENDING_FLAG) {
+        notify(TouchEvent::TimedOut);
+        return Err(YubicoError::Timeout);
+    }
+    if touch_pending {
+        notify(TouchEvent::Touched)
+    }
+
+    let mut r0 = 7;
+    while r0 + 8 <= response.len() {
+        if read(transport, &mut response[r0..r0 + 8])? < 8 {
             break;
         }
         let flags = Flags::from_bits_truncate(response[r0 + 7]);
@@ -200,7 +301,7 @@ pub fn read_response(
         }
         r0 += 7;
     }
-    write_reset(handle)?;
+    write_reset(transport, deadline)?;
     Ok(r0)
 }
 
@@ -225,3 +326,66 @@ impl Frame {
         f
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Device;
+
+    /// A key stuck on a pending write, recording the packets it gets.
+    struct BusyKey {
+        written: Vec<[u8; 8]>,
+    }
+
+    impl Transport for BusyKey {
+        fn devices(&mut self) -> Result<Vec<Device>, YubicoError> {
+            Ok(Vec::new())
+        }
+
+        fn open(&mut self, _: &Device) -> Result<(), YubicoError> {
+            Ok(())
+        }
+
+        fn close(&mut self) -> Result<(), YubicoError> {
+            Ok(())
+        }
+
+        fn get_feature_report(&mut self, buf: &mut [u8; 8]) -> Result<usize, YubicoError> {
+            *buf = [0, 5, 4, 3, 1, 0, 0, Flags::SLOT_WRITE_FLAG.bits()];
+            Ok(8)
+        }
+
+        fn set_feature_report(&mut self, packet: &[u8; 8]) -> Result<(), YubicoError> {
+            self.written.push(*packet);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn timeout_sends_a_reset_packet() {
+        let mut key = BusyKey {
+            written: Vec::new(),
+        };
+        let deadline = Deadline::new(Some(Duration::from_millis(0)), None);
+        match read_status(&mut key, &deadline) {
+            Err(YubicoError::Timeout) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(key.written, [RESET_PACKET]);
+    }
+
+    #[test]
+    fn cancellation_sends_a_reset_packet() {
+        let mut key = BusyKey {
+            written: Vec::new(),
+        };
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+        let deadline = Deadline::new(None, Some(cancellation));
+        match read_status(&mut key, &deadline) {
+            Err(YubicoError::Cancelled) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(key.written, [RESET_PACKET]);
+    }
+}
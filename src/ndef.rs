@@ -0,0 +1,160 @@
+//! NDEF records sent over NFC by the YubiKey NEO and NFC-capable keys.
+
+use crate::yubicoerror::YubicoError;
+
+const NDEF_DATA_SIZE: usize = 54;
+pub(crate) const SIZEOF_NDEF: usize = NDEF_DATA_SIZE + 2;
+
+const NDEF_TYPE_URI: u8 = b'U';
+const NDEF_TYPE_TEXT: u8 = b'T';
+
+/// The URI prefixes abbreviated by the NFC Forum URI record type, in
+/// the order of their identifier codes (starting at 1).
+const URI_PREFIXES: [&str; 35] = [
+    "http://www.",
+    "https://www.",
+    "http://",
+    "https://",
+    "tel:",
+    "mailto:",
+    "ftp://anonymous:anonymous@",
+    "ftp://ftp.",
+    "ftps://",
+    "sftp://",
+    "smb://",
+    "nfs://",
+    "ftp://",
+    "dav://",
+    "news:",
+    "telnet://",
+    "imap:",
+    "rtsp://",
+    "urn:",
+    "pop:",
+    "sip:",
+    "sips:",
+    "tftp:",
+    "btspp://",
+    "btl2cap://",
+    "btgoep://",
+    "tcpobex://",
+    "irdaobex://",
+    "file://",
+    "urn:epc:id:",
+    "urn:epc:tag:",
+    "urn:epc:pat:",
+    "urn:epc:raw:",
+    "urn:epc:",
+    "urn:nfc:",
+];
+
+/// The content of an NDEF record.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NdefPayload {
+    /// A URI, opened by the phone reading the key. Standard prefixes
+    /// such as "https://" are compressed to a single byte.
+    Uri(String),
+    /// A text, with its IETF language code ("en", "de-CH"...).
+    Text { text: String, language: String },
+}
+
+impl NdefPayload {
+    /// Encodes the record as the length, type and data fields the
+    /// firmware expects.
+    pub fn to_bytes(&self) -> Result<[u8; SIZEOF_NDEF], YubicoError> {
+        let mut data = Vec::with_capacity(NDEF_DATA_SIZE);
+        let ndef_type = match *self {
+            NdefPayload::Uri(ref uri) => {
+                // "urn:" also starts the longer "urn:epc:..." prefixes.
+                let prefix = (0..URI_PREFIXES.len())
+                    .filter(|&i| uri.starts_with(URI_PREFIXES[i]))
+                    .max_by_key(|&i| URI_PREFIXES[i].len());
+                match prefix {
+                    Some(i) => {
+                        data.push(i as u8 + 1);
+                        data.extend_from_slice(&uri.as_bytes()[URI_PREFIXES[i].len()..]);
+                    }
+                    None => {
+                        data.push(0);
+                        data.extend_from_slice(uri.as_bytes());
+                    }
+                }
+                NDEF_TYPE_URI
+            }
+            NdefPayload::Text {
+                ref text,
+                ref language,
+            } => {
+                // The status byte holds the length of the language
+                // code; the text is UTF-8.
+                if language.len() > 0x3f {
+                    return Err(YubicoError::NdefTooLong);
+                }
+                data.push(language.len() as u8);
+                data.extend_from_slice(language.as_bytes());
+                data.extend_from_slice(text.as_bytes());
+                NDEF_TYPE_TEXT
+            }
+        };
+
+        if data.len() > NDEF_DATA_SIZE {
+            return Err(YubicoError::NdefTooLong);
+        }
+
+        let mut ndef = [0; SIZEOF_NDEF];
+        ndef[0] = data.len() as u8;
+        ndef[1] = ndef_type;
+        ndef[2..2 + data.len()].copy_from_slice(&data);
+        Ok(ndef)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(uri: &str) -> Vec<u8> {
+        let ndef = NdefPayload::Uri(uri.to_string()).to_bytes().unwrap();
+        assert_eq!(ndef[1], NDEF_TYPE_URI);
+        ndef[2..2 + ndef[0] as usize].to_vec()
+    }
+
+    #[test]
+    fn uri_prefixes_are_compressed() {
+        assert_eq!(uri("https://my.yubico.com"), b"\x04my.yubico.com");
+        assert_eq!(uri("http://www.yubico.com"), b"\x01yubico.com");
+        assert_eq!(uri("mailto:a@b.c"), b"\x06a@b.c");
+        assert_eq!(uri("urn:nfc:sn"), b"\x23sn");
+    }
+
+    #[test]
+    fn longest_uri_prefix_wins() {
+        assert_eq!(uri("urn:epc:id:sgtin"), b"\x1esgtin");
+        assert_eq!(uri("urn:isbn"), b"\x13isbn");
+        assert_eq!(uri("ftp://ftp.example.org"), b"\x08example.org");
+    }
+
+    #[test]
+    fn unknown_uri_scheme_is_kept() {
+        assert_eq!(uri("otpauth://totp"), b"\x00otpauth://totp");
+    }
+
+    #[test]
+    fn text_records_the_language_length() {
+        let text = NdefPayload::Text {
+            text: "hi".to_string(),
+            language: "en".to_string(),
+        };
+        let ndef = text.to_bytes().unwrap();
+        assert_eq!(ndef[..7], [5, NDEF_TYPE_TEXT, 2, b'e', b'n', b'h', b'i']);
+    }
+
+    #[test]
+    fn long_records_are_rejected() {
+        let uri = NdefPayload::Uri(format!("https://{}", "a".repeat(NDEF_DATA_SIZE)));
+        match uri.to_bytes() {
+            Err(YubicoError::NdefTooLong) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,513 @@
+use crate::config::{Command, Config, DeviceMode, Slot};
+use crate::configure::{DeviceModeConfig, UpdateConfig, SIZEOF_CONFIG};
+use crate::deviceinfo::{parse_tlvs, DeviceConfigUpdate, DeviceInfo, TAG_MORE_DATA};
+use crate::hmacmode::Hmac;
+use crate::keyboard::{ScanMap, SCAN_MAP_SIZE};
+use crate::manager::{self, Deadline, Frame};
+use crate::ndef::{NdefPayload, SIZEOF_NDEF};
+use crate::otpmode::Aes128Block;
+use crate::sec::{crc16, CRC_RESIDUAL_OK};
+use crate::status::{DeviceStatus, SlotStatus};
+use crate::transport::{Transport, UsbTransport};
+use crate::yubicoerror::YubicoError;
+use crate::Result;
+use aes::cipher::generic_array::GenericArray;
+
+/// Lets the CCID interface be ejected by touching the key.
+const MODE_FLAG_EJECT: u8 = 0x80;
+
+/// The most device information pages read before giving up on a key
+/// that keeps announcing more data.
+const MAX_DEVICE_INFO_PAGES: u8 = 16;
+
+/// A device kept open across commands, returned by `Yubico::open`.
+///
+/// Opening a device detaches the kernel driver of its OTP interface, and
+/// closing it reattaches it: a session pays for it once, instead of
+/// once per command. The device is closed when the session is dropped,
+/// panics included, or explicitly by `close`, which reports errors.
+///
+/// The commands take the same arguments as their `Yubico`
+/// counterparts; the device selection fields of `conf` are ignored.
+pub struct YubikeySession<'a, T: Transport = UsbTransport> {
+    transport: &'a mut T,
+    status: &'a mut Option<DeviceStatus>,
+    open: bool,
+}
+
+impl<'a, T: Transport> YubikeySession<'a, T> {
+    pub(crate) fn new(transport: &'a mut T, status: &'a mut Option<DeviceStatus>) -> Self {
+        YubikeySession {
+            transport,
+            status,
+            open: true,
+        }
+    }
+
+    /// Closes the device, reattaching the kernel drivers.
+    pub fn close(mut self) -> Result<()> {
+        self.open = false;
+        self.transport.close()
+    }
+
+    /// Reads the status block of the device: firmware version,
+    /// programming sequence and touch level.
+    pub fn read_status(&mut self, conf: Config) -> Result<DeviceStatus> {
+        let deadline = Deadline::from_config(&conf);
+        let status = manager::read_status(self.transport, &deadline)?;
+        *self.status = Some(status);
+        Ok(status)
+    }
+
+    /// Reads whether `slot` is configured, and whether it is triggered
+    /// by touch.
+    pub fn slot_status(&mut self, conf: Config, slot: Slot) -> Result<SlotStatus> {
+        let status = self.read_status(conf)?;
+        Ok(status.slot_status(&slot))
+    }
+
+    pub fn write_config(
+        &mut self,
+        conf: Config,
+        device_config: &mut DeviceModeConfig,
+    ) -> Result<()> {
+        self.write_config_with_access_code(conf, device_config, None)
+    }
+
+    /// Writes a configuration to a slot protected by `access_code`.
+    pub fn write_config_with_access_code(
+        &mut self,
+        conf: Config,
+        device_config: &mut DeviceModeConfig,
+        access_code: Option<&[u8; 6]>,
+    ) -> Result<()> {
+        let d = device_config.to_frame_with_access_code(conf.command, access_code);
+
+        let deadline = Deadline::from_config(&conf);
+        let status = manager::write_config(self.transport, &d, &deadline)?;
+        *self.status = Some(status);
+        Ok(())
+    }
+
+    /// Swaps the configurations of slot 1 and slot 2. `access_code` is
+    /// the current access code of the slots, if they are protected.
+    pub fn swap_slots(&mut self, conf: Config, access_code: Option<&[u8; 6]>) -> Result<()> {
+        let d = Frame::new(empty_config(access_code), Command::Swap);
+
+        let deadline = Deadline::from_config(&conf);
+        let status = manager::write_config(self.transport, &d, &deadline)?;
+        *self.status = Some(status);
+        Ok(())
+    }
+
+    /// Changes the output settings of a configured slot, keeping its
+    /// secrets. Every updatable flag is replaced by those of `update`.
+    /// `access_code` is the current access code of the slot, if it is
+    /// protected.
+    pub fn update_slot(
+        &mut self,
+        conf: Config,
+        slot: Slot,
+        update: &UpdateConfig,
+        access_code: Option<&[u8; 6]>,
+    ) -> Result<()> {
+        self.change_access_code(conf, slot, update, access_code, access_code)
+    }
+
+    /// Sets, changes or removes the access code of a configured slot,
+    /// while applying `update` to it: every updatable flag is replaced,
+    /// so pass the flags the slot should keep (`UpdateConfig::default()`
+    /// keeps it updatable, with a visible serial). `access_code` is the
+    /// current access code of the slot, and `new_access_code` the one it
+    /// is protected by afterwards (`None` to remove the protection).
+    pub fn change_access_code(
+        &mut self,
+        conf: Config,
+        slot: Slot,
+        update: &UpdateConfig,
+        access_code: Option<&[u8; 6]>,
+        new_access_code: Option<&[u8; 6]>,
+    ) -> Result<()> {
+        let command = match slot {
+            Slot::Slot1 => Command::Update1,
+            Slot::Slot2 => Command::Update2,
+        };
+        let d = update.to_frame(command, access_code, new_access_code);
+
+        let deadline = Deadline::from_config(&conf);
+        let status = manager::write_config(self.transport, &d, &deadline)?;
+        *self.status = Some(status);
+        Ok(())
+    }
+
+    /// Erases the configuration of a slot. `access_code` is the current
+    /// access code of the slot, if it is protected.
+    pub fn delete_slot(
+        &mut self,
+        conf: Config,
+        slot: Slot,
+        access_code: Option<&[u8; 6]>,
+    ) -> Result<()> {
+        let command = match slot {
+            Slot::Slot1 => Command::Configuration1,
+            Slot::Slot2 => Command::Configuration2,
+        };
+        let d = Frame::new(empty_config(access_code), command);
+
+        let deadline = Deadline::from_config(&conf);
+        let status = manager::write_config(self.transport, &d, &deadline)?;
+        *self.status = Some(status);
+        if status.is_configured(&slot) {
+            return Err(YubicoError::ConfigNotWritten);
+        }
+        Ok(())
+    }
+
+    /// Selects the USB interfaces exposed by a YubiKey NEO or YubiKey
+    /// 4, the time the key waits for a touch in challenge-response mode
+    /// and, in CCID mode, the time after which the smart card is
+    /// ejected (0 disables ejecting). Times are in seconds. The key has
+    /// to be re-inserted for the new mode to apply.
+    pub fn set_device_mode(
+        &mut self,
+        conf: Config,
+        mode: DeviceMode,
+        cr_timeout: u8,
+        auto_eject_time: u16,
+    ) -> Result<()> {
+        let mut payload = [0; 64];
+        payload[0] = mode as u8;
+        if mode == DeviceMode::Ccid && auto_eject_time > 0 {
+            payload[0] |= MODE_FLAG_EJECT;
+        }
+        payload[1] = cr_timeout;
+        payload[2..4].copy_from_slice(&auto_eject_time.to_le_bytes());
+        let d = Frame::new(payload, Command::DeviceConfig);
+
+        self.write_config_since(&conf, &d, (3, 0, 0))
+    }
+
+    /// Reads the device information of a YubiKey 4.1 or later:
+    /// supported and enabled applications, form factor, serial...
+    pub fn read_device_info(&mut self, conf: Config) -> Result<DeviceInfo> {
+        let deadline = Deadline::from_config(&conf);
+        let status = manager::read_status(self.transport, &deadline)?;
+        *self.status = Some(status);
+        if status.version < (4, 1, 0) {
+            return Err(YubicoError::CommandNotSupported);
+        }
+
+        // Large answers are split in pages, all but the last one
+        // flagged with a "more data" TLV.
+        let mut data = Vec::new();
+        for page in 0..MAX_DEVICE_INFO_PAGES {
+            let tlvs = manager::read_device_info_page(self.transport, page, &deadline)?;
+            let more = parse_tlvs(&tlvs)?
+                .iter()
+                .any(|&(tag, value)| tag == TAG_MORE_DATA && value == [1]);
+            data.extend_from_slice(&tlvs);
+            if !more {
+                return DeviceInfo::parse(&data);
+            }
+        }
+        Err(YubicoError::InvalidResponse)
+    }
+
+    /// Writes the device configuration of a YubiKey 5: enabled USB and
+    /// NFC applications, timeouts, device flags and lock code. Unless
+    /// the update reboots the key, the changes apply once it is
+    /// re-inserted.
+    ///
+    /// A rebooting update can not be verified: the key drops off the
+    /// bus before it can be asked whether it accepted the write, so
+    /// `Ok` only means that the update was sent. Read the device info
+    /// once the key is back to check it.
+    pub fn write_device_config(&mut self, conf: Config, update: &DeviceConfigUpdate) -> Result<()> {
+        let data = update.to_bytes();
+        let mut payload = [0; 64];
+        payload[..data.len()].copy_from_slice(&data);
+        let d = Frame::new(payload, Command::WriteDeviceConfig);
+
+        let deadline = Deadline::from_config(&conf);
+        let status = manager::read_status(self.transport, &deadline)?;
+        let status = if status.version < (5, 0, 0) {
+            return Err(YubicoError::CommandNotSupported);
+        } else if update.reboot {
+            // The key drops off the bus, and can not be asked for its
+            // status any more.
+            manager::write_frame(self.transport, &d, &deadline)?;
+            status
+        } else {
+            manager::write_config(self.transport, &d, &deadline)?
+        };
+        *self.status = Some(status);
+        Ok(())
+    }
+
+    /// Programs the NDEF record sent over NFC when `slot` is
+    /// triggered. `access_code` is the current access code of the
+    /// slot, if it is protected.
+    pub fn write_ndef(
+        &mut self,
+        conf: Config,
+        slot: Slot,
+        payload: &NdefPayload,
+        access_code: Option<&[u8; 6]>,
+    ) -> Result<()> {
+        let command = match slot {
+            Slot::Slot1 => Command::Ndef1,
+            Slot::Slot2 => Command::Ndef2,
+        };
+        let mut data = [0; 64];
+        data[..SIZEOF_NDEF].copy_from_slice(&payload.to_bytes()?);
+        if let Some(code) = access_code {
+            data[SIZEOF_NDEF..SIZEOF_NDEF + code.len()].copy_from_slice(code);
+        }
+        let d = Frame::new(data, command);
+
+        // NDEF was introduced with the YubiKey NEO.
+        self.write_config_since(&conf, &d, (3, 0, 0))
+    }
+
+    /// Replaces the scan codes the key types its output with, for hosts
+    /// whose keyboard layout mangles the default output.
+    pub fn write_scan_map(&mut self, conf: Config, scan_map: &ScanMap) -> Result<()> {
+        let mut data = [0; 64];
+        data[..SCAN_MAP_SIZE].copy_from_slice(scan_map.as_bytes());
+        let d = Frame::new(data, Command::ScanMap);
+
+        self.write_config_since(&conf, &d, (3, 0, 0))
+    }
+
+    /// Writes a frame that firmware older than `version` does not know.
+    fn write_config_since(
+        &mut self,
+        conf: &Config,
+        d: &Frame,
+        version: (u8, u8, u8),
+    ) -> Result<()> {
+        let deadline = Deadline::from_config(conf);
+        let status = manager::read_status(self.transport, &deadline)?;
+        if status.version < version {
+            return Err(YubicoError::CommandNotSupported);
+        }
+        let status = manager::write_config(self.transport, d, &deadline)?;
+        *self.status = Some(status);
+        Ok(())
+    }
+
+    pub fn read_serial_number(&mut self, conf: Config) -> Result<u32> {
+        let deadline = Deadline::from_config(&conf);
+        manager::read_serial(self.transport, &deadline)
+    }
+
+    pub fn challenge_response_hmac(&mut self, chall: &[u8], conf: Config) -> Result<Hmac> {
+        let mut hmac = Hmac([0; 20]);
+        let mut challenge = [0; 64];
+
+        if conf.variable && chall.last() == Some(&0) {
+            challenge = [0xff; 64];
+        }
+
+        let mut command = Command::ChallengeHmac1;
+        if let Slot::Slot2 = conf.slot {
+            command = Command::ChallengeHmac2;
+        }
+
+        challenge[..chall.len()].copy_from_slice(chall);
+        let d = Frame::new(challenge, command);
+
+        // Read the response.
+        let mut response = [0; 36];
+        self.challenge_response(&conf, &d, &mut response)?;
+
+        // Check response.
+        if crc16(&response[..22]) != CRC_RESIDUAL_OK {
+            return Err(YubicoError::WrongCRC);
+        }
+
+        hmac.0.clone_from_slice(&response[..20]);
+
+        Ok(hmac)
+    }
+
+    pub fn challenge_response_otp(&mut self, chall: &[u8], conf: Config) -> Result<Aes128Block> {
+        let mut block = Aes128Block {
+            block: GenericArray::clone_from_slice(&[0; 16]),
+        };
+        let mut challenge = [0; 64];
+
+        let mut command = Command::ChallengeOtp1;
+        if let Slot::Slot2 = conf.slot {
+            command = Command::ChallengeOtp2;
+        }
+
+        challenge[..chall.len()].copy_from_slice(chall);
+        let d = Frame::new(challenge, command);
+
+        let mut response = [0; 36];
+        self.challenge_response(&conf, &d, &mut response)?;
+
+        // Check response.
+        if crc16(&response[..18]) != CRC_RESIDUAL_OK {
+            return Err(YubicoError::WrongCRC);
+        }
+
+        block.block.copy_from_slice(&response[..16]);
+
+        Ok(block)
+    }
+
+    /// Sends a challenge and reads the response, waiting for a touch if
+    /// the slot requires one, until the deadline of `conf`.
+    fn challenge_response(&mut self, conf: &Config, d: &Frame, response: &mut [u8]) -> Result<()> {
+        let deadline = Deadline::from_config(conf);
+
+        let mut buf = [0; 8];
+        manager::wait(
+            self.transport,
+            |f| !f.contains(manager::Flags::SLOT_WRITE_FLAG),
+            &mut buf,
+            &deadline,
+        )?;
+        manager::write_frame(self.transport, d, &deadline)?;
+        manager::read_response(
+            self.transport,
+            response,
+            &deadline,
+            conf.touch_callback.as_ref(),
+        )?;
+        Ok(())
+    }
+}
+
+impl<T: Transport> Drop for YubikeySession<'_, T> {
+    fn drop(&mut self) {
+        if self.open {
+            let _ = self.transport.close();
+        }
+    }
+}
+
+/// The payload of an all-zero configuration, followed by the current
+/// access code: erases a slot, or carries no data for a swap.
+fn empty_config(access_code: Option<&[u8; 6]>) -> [u8; 64] {
+    let mut payload = [0; 64];
+    if let Some(code) = access_code {
+        payload[SIZEOF_CONFIG..SIZEOF_CONFIG + code.len()].copy_from_slice(code);
+    }
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deviceinfo::{TAG_SERIAL, TAG_VERSION};
+    use crate::Device;
+
+    /// A YubiKey 5 answering device info requests with canned pages;
+    /// the last one is repeated for any later page.
+    struct PagedKey {
+        pages: Vec<Vec<u8>>,
+        requested: Vec<u8>,
+        frame: [u8; 70],
+        response: Vec<u8>,
+        response_seq: Option<usize>,
+    }
+
+    impl PagedKey {
+        fn new(pages: Vec<Vec<u8>>) -> Self {
+            PagedKey {
+                pages,
+                requested: Vec::new(),
+                frame: [0; 70],
+                response: Vec::new(),
+                response_seq: None,
+            }
+        }
+
+        fn process_frame(&mut self) {
+            if self.frame[64] != Command::DeviceInfo as u8 {
+                return;
+            }
+            let page = self.frame[0];
+            self.requested.push(page);
+            let tlvs = &self.pages[(page as usize).min(self.pages.len() - 1)];
+            self.response = vec![tlvs.len() as u8];
+            self.response.extend_from_slice(tlvs);
+            let crc = !crc16(&self.response);
+            self.response.extend_from_slice(&crc.to_le_bytes());
+            let padding = (7 - self.response.len() % 7) % 7;
+            self.response.resize(self.response.len() + padding, 0);
+            self.response_seq = Some(0);
+        }
+    }
+
+    impl Transport for PagedKey {
+        fn devices(&mut self) -> Result<Vec<Device>> {
+            Ok(Vec::new())
+        }
+
+        fn open(&mut self, _: &Device) -> Result<()> {
+            Ok(())
+        }
+
+        fn close(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn get_feature_report(&mut self, buf: &mut [u8; 8]) -> Result<usize> {
+            match self.response_seq {
+                Some(seq) => {
+                    let seq = seq % (self.response.len() / 7);
+                    buf[..7].copy_from_slice(&self.response[seq * 7..seq * 7 + 7]);
+                    buf[7] = manager::Flags::RESP_PENDING_FLAG.bits() | seq as u8;
+                    self.response_seq = Some(seq + 1);
+                }
+                None => buf.copy_from_slice(&[0, 5, 4, 3, 1, 0, 0, 0]),
+            }
+            Ok(8)
+        }
+
+        fn set_feature_report(&mut self, packet: &[u8; 8]) -> Result<()> {
+            let seq = (packet[7] & 0x1f) as usize;
+            if seq == 0x0f {
+                self.response_seq = None;
+            } else {
+                self.frame[seq * 7..seq * 7 + 7].copy_from_slice(&packet[..7]);
+                if seq * 7 + 7 == self.frame.len() {
+                    self.process_frame()
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn read_device_info(key: &mut PagedKey) -> Result<DeviceInfo> {
+        let mut status = None;
+        let mut session = YubikeySession::new(key, &mut status);
+        session.read_device_info(Config::default_config())
+    }
+
+    #[test]
+    fn device_info_spans_pages() {
+        let mut key = PagedKey::new(vec![
+            vec![TAG_SERIAL, 4, 0, 0x12, 0xd6, 0x87, TAG_MORE_DATA, 1, 1],
+            vec![TAG_VERSION, 3, 5, 4, 3],
+        ]);
+        let info = read_device_info(&mut key).unwrap();
+        assert_eq!(key.requested, [0, 1]);
+        assert_eq!(info.serial, Some(1_234_567));
+        assert_eq!(info.version, Some((5, 4, 3)));
+    }
+
+    #[test]
+    fn device_info_pages_are_capped() {
+        let mut key = PagedKey::new(vec![vec![TAG_MORE_DATA, 1, 1]]);
+        match read_device_info(&mut key) {
+            Err(YubicoError::InvalidResponse) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(key.requested.len(), MAX_DEVICE_INFO_PAGES as usize);
+    }
+}
@@ -0,0 +1,65 @@
+use crate::config::Slot;
+
+pub(crate) const CONFIG1_VALID: u16 = 0x01;
+pub(crate) const CONFIG2_VALID: u16 = 0x02;
+pub(crate) const CONFIG1_TOUCH: u16 = 0x04;
+pub(crate) const CONFIG2_TOUCH: u16 = 0x08;
+pub(crate) const LED_INV: u16 = 0x10;
+
+/// The state of a configuration slot.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SlotStatus {
+    /// A configuration is stored in the slot.
+    pub configured: bool,
+    /// The slot is triggered by touching the key, rather than by a
+    /// challenge sent by the host.
+    pub touch_required: bool,
+}
+
+/// The status block the YubiKey returns in its feature reports.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeviceStatus {
+    /// Firmware version, as `(major, minor, build)`.
+    pub version: (u8, u8, u8),
+    /// Programming sequence, incremented on each successful configuration.
+    pub pgm_seq: u8,
+    /// Slot and touch state bits.
+    pub touch_level: u16,
+}
+
+impl DeviceStatus {
+    /// Parses the status block from a feature report (bytes 1 to 6).
+    pub fn from_report(report: &[u8]) -> Self {
+        DeviceStatus {
+            version: (report[1], report[2], report[3]),
+            pgm_seq: report[4],
+            touch_level: u16::from_le_bytes([report[5], report[6]]),
+        }
+    }
+
+    /// Returns `true` if a configuration is stored in `slot`.
+    pub fn is_configured(&self, slot: &Slot) -> bool {
+        let valid = match *slot {
+            Slot::Slot1 => CONFIG1_VALID,
+            Slot::Slot2 => CONFIG2_VALID,
+        };
+        self.touch_level & valid != 0
+    }
+
+    /// Decodes the state of `slot` from the touch level.
+    pub fn slot_status(&self, slot: &Slot) -> SlotStatus {
+        let touch = match *slot {
+            Slot::Slot1 => CONFIG1_TOUCH,
+            Slot::Slot2 => CONFIG2_TOUCH,
+        };
+        SlotStatus {
+            configured: self.is_configured(slot),
+            touch_required: self.touch_level & touch != 0,
+        }
+    }
+
+    /// Returns `true` if the LED is inverted (`ExtendedFlags::LED_INV`).
+    pub fn is_led_inverted(&self) -> bool {
+        self.touch_level & LED_INV != 0
+    }
+}
@@ -0,0 +1,224 @@
+use crate::yubicoerror::YubicoError;
+use crate::{Device, VENDOR_ID};
+use rusb::{request_type, Context, DeviceHandle, Direction, Recipient, RequestType, UsbContext};
+use std::time::Duration;
+
+const HID_GET_REPORT: u8 = 0x01;
+const HID_SET_REPORT: u8 = 0x09;
+const REPORT_TYPE_FEATURE: u16 = 0x03;
+
+/// The OTP application is the HID boot keyboard interface of the key.
+const CLASS_HID: u8 = 0x03;
+const SUBCLASS_BOOT: u8 = 0x01;
+const PROTOCOL_KEYBOARD: u8 = 0x01;
+
+/// A channel to the OTP interface of a YubiKey.
+///
+/// The YubiKey talks to the host through 8-byte HID feature reports;
+/// everything above that (framing, CRC, flow control) is handled by
+/// the crate, so a transport only has to move those reports around.
+pub trait Transport {
+    /// Lists the YubiKeys reachable through this transport.
+    fn devices(&mut self) -> Result<Vec<Device>, YubicoError>;
+
+    /// Opens a device returned by `devices`.
+    fn open(&mut self, device: &Device) -> Result<(), YubicoError>;
+
+    /// Releases the device opened by `open`.
+    fn close(&mut self) -> Result<(), YubicoError>;
+
+    /// Reads an 8-byte feature report, returning the number of bytes read.
+    fn get_feature_report(&mut self, buf: &mut [u8; 8]) -> Result<usize, YubicoError>;
+
+    /// Writes an 8-byte feature report.
+    fn set_feature_report(&mut self, packet: &[u8; 8]) -> Result<(), YubicoError>;
+}
+
+/// The default transport, using libusb through `rusb`.
+///
+/// Only the OTP interface is claimed, so that the FIDO and CCID
+/// interfaces of the key stay usable by other programs meanwhile.
+pub struct UsbTransport {
+    context: Context,
+    handle: Option<DeviceHandle<Context>>,
+    /// The number of the OTP interface of the open device.
+    interface: u8,
+    /// Set if the kernel driver of the interface was detached, and has
+    /// to be reattached on close.
+    detached: bool,
+}
+
+impl UsbTransport {
+    /// Creates a new libusb context.
+    pub fn new() -> Result<Self, YubicoError> {
+        Ok(UsbTransport {
+            context: Context::new()?,
+            handle: None,
+            interface: 0,
+            detached: false,
+        })
+    }
+
+    fn handle(&self) -> Result<&DeviceHandle<Context>, YubicoError> {
+        self.handle.as_ref().ok_or(YubicoError::OpenDeviceError)
+    }
+}
+
+impl Transport for UsbTransport {
+    fn devices(&mut self) -> Result<Vec<Device>, YubicoError> {
+        let mut result = Vec::new();
+        for device in self.context.devices()?.iter() {
+            let descr = device.device_descriptor()?;
+            if descr.vendor_id() == VENDOR_ID {
+                result.push(Device {
+                    product_id: descr.product_id(),
+                    vendor_id: descr.vendor_id(),
+                    bus_number: device.bus_number(),
+                    address: device.address(),
+                    serial: None,
+                    version: None,
+                });
+            }
+        }
+        Ok(result)
+    }
+
+    fn open(&mut self, yubikey: &Device) -> Result<(), YubicoError> {
+        let devices = match self.context.devices() {
+            Ok(device) => device,
+            Err(_) => {
+                return Err(YubicoError::DeviceNotFound);
+            }
+        };
+
+        for device in devices.iter() {
+            let device_desc = match device.device_descriptor() {
+                Ok(device) => device,
+                Err(_) => {
+                    return Err(YubicoError::DeviceNotFound);
+                }
+            };
+
+            if device_desc.vendor_id() == yubikey.vendor_id
+                && device_desc.product_id() == yubikey.product_id
+                && device.bus_number() == yubikey.bus_number
+                && device.address() == yubikey.address
+            {
+                let config = device.config_descriptor(0)?;
+                let interface = config
+                    .interfaces()
+                    .flat_map(|interface| interface.descriptors())
+                    .find(|descr| {
+                        descr.class_code() == CLASS_HID
+                            && descr.sub_class_code() == SUBCLASS_BOOT
+                            && descr.protocol_code() == PROTOCOL_KEYBOARD
+                    })
+                    // The OTP application is disabled over USB.
+                    .ok_or(YubicoError::DeviceNotFound)?
+                    .interface_number();
+
+                let handle = match device.open() {
+                    Ok(handle) => handle,
+                    Err(_) => return Err(YubicoError::OpenDeviceError),
+                };
+
+                let detached = claim_interface(&handle, interface, config.number())?;
+
+                self.handle = Some(handle);
+                self.interface = interface;
+                self.detached = detached;
+                return Ok(());
+            }
+        }
+
+        Err(YubicoError::DeviceNotFound)
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
+    fn close(&mut self) -> Result<(), YubicoError> {
+        self.handle = None;
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    fn close(&mut self) -> Result<(), YubicoError> {
+        if let Some(handle) = self.handle.take() {
+            handle.release_interface(self.interface)?;
+            if self.detached {
+                self.detached = false;
+                handle.attach_kernel_driver(self.interface)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_feature_report(&mut self, buf: &mut [u8; 8]) -> Result<usize, YubicoError> {
+        let reqtype = request_type(Direction::In, RequestType::Class, Recipient::Interface);
+        let value = REPORT_TYPE_FEATURE << 8;
+        Ok(self.handle()?.read_control(
+            reqtype,
+            HID_GET_REPORT,
+            value,
+            self.interface as u16,
+            buf,
+            Duration::new(2, 0),
+        )?)
+    }
+
+    fn set_feature_report(&mut self, packet: &[u8; 8]) -> Result<(), YubicoError> {
+        let reqtype = request_type(Direction::Out, RequestType::Class, Recipient::Interface);
+        let value = REPORT_TYPE_FEATURE << 8;
+        if self.handle()?.write_control(
+            reqtype,
+            HID_SET_REPORT,
+            value,
+            self.interface as u16,
+            packet,
+            Duration::new(2, 0),
+        )? != 8
+        {
+            Err(YubicoError::CanNotWriteToDevice)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Detaches the kernel driver of `interface`, if any, and claims it.
+/// Returns whether a driver was detached.
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn claim_interface(
+    handle: &DeviceHandle<Context>,
+    interface: u8,
+    config: u8,
+) -> Result<bool, YubicoError> {
+    let detached = handle.kernel_driver_active(interface).unwrap_or(false);
+    if detached {
+        handle.detach_kernel_driver(interface)?;
+    }
+
+    let claimed = handle.active_configuration().and_then(|active| {
+        if active != config {
+            handle.set_active_configuration(config)?;
+        }
+        handle.claim_interface(interface)
+    });
+    if let Err(error) = claimed {
+        if detached {
+            let _ = handle.attach_kernel_driver(interface);
+        }
+        return Err(error.into());
+    }
+    Ok(detached)
+}
+
+/// The system driver keeps the interface, and feature reports go
+/// through it.
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+fn claim_interface(
+    _handle: &DeviceHandle<Context>,
+    _interface: u8,
+    _config: u8,
+) -> Result<bool, YubicoError> {
+    Ok(false)
+}
@@ -2,6 +2,8 @@ use rusb::Error as usbError;
 use std::error;
 use std::fmt;
 use std::io::Error as ioError;
+#[cfg(feature = "pcsc")]
+use std::io::ErrorKind;
 
 #[derive(Debug)]
 pub enum YubicoError {
@@ -13,6 +15,17 @@ pub enum YubicoError {
     CanNotWriteToDevice,
     WrongCRC,
     ConfigNotWritten,
+    UnsupportedCharacter(char),
+    PasswordTooLong,
+    PublicIdTooLong,
+    InvalidOathDigits(u8),
+    InvalidMovingFactor(u32),
+    TokenIdTooLong,
+    InvalidResponse,
+    NdefTooLong,
+    Timeout,
+    Cancelled,
+    SmartCardError(u16),
 }
 
 impl fmt::Display for YubicoError {
@@ -26,6 +39,29 @@ impl fmt::Display for YubicoError {
             YubicoError::WrongCRC => write!(f, "Wrong CRC"),
             YubicoError::CanNotWriteToDevice => write!(f, "Can not write to Device"),
             YubicoError::ConfigNotWritten => write!(f, "Configuration has failed"),
+            YubicoError::UnsupportedCharacter(c) => {
+                write!(
+                    f,
+                    "Character {:?} can not be typed on this keyboard layout",
+                    c
+                )
+            }
+            YubicoError::PasswordTooLong => write!(f, "Password is too long"),
+            YubicoError::PublicIdTooLong => write!(f, "Public identity is too long"),
+            YubicoError::InvalidOathDigits(digits) => {
+                write!(f, "OATH-HOTP codes have 6 or 8 digits, not {}", digits)
+            }
+            YubicoError::InvalidMovingFactor(seed) => write!(
+                f,
+                "Moving factor seed {} is not a multiple of 16 up to 1048560",
+                seed
+            ),
+            YubicoError::TokenIdTooLong => write!(f, "OATH token id is too long"),
+            YubicoError::InvalidResponse => write!(f, "Invalid response from the device"),
+            YubicoError::NdefTooLong => write!(f, "NDEF record is too long"),
+            YubicoError::Timeout => write!(f, "Timed out waiting for the device"),
+            YubicoError::Cancelled => write!(f, "Operation cancelled"),
+            YubicoError::SmartCardError(sw) => write!(f, "Smart card error: status {:04x}", sw),
         }
     }
 }
@@ -50,3 +86,17 @@ impl From<usbError> for YubicoError {
         YubicoError::UsbError(err)
     }
 }
+
+#[cfg(feature = "pcsc")]
+impl From<pcsc::Error> for YubicoError {
+    fn from(err: pcsc::Error) -> YubicoError {
+        match err {
+            pcsc::Error::NoReadersAvailable
+            | pcsc::Error::UnknownReader
+            | pcsc::Error::ReaderUnavailable
+            | pcsc::Error::NoSmartcard
+            | pcsc::Error::RemovedCard => YubicoError::DeviceNotFound,
+            err => YubicoError::IOError(ioError::new(ErrorKind::Other, err)),
+        }
+    }
+}
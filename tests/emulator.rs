@@ -0,0 +1,384 @@
+#![cfg(feature = "emulator")]
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use yubico_manager::config::{
+    CancellationToken, Command, Config, DeviceMode, Mode, Slot, TouchEvent,
+};
+use yubico_manager::configure::{
+    ConfigFlags, DeviceModeConfig, ExtendedFlags, TicketFlags, UpdateConfig,
+};
+use yubico_manager::deviceinfo::{Capabilities, DeviceConfigUpdate};
+use yubico_manager::emulator::Emulator;
+use yubico_manager::hmacmode::HmacKey;
+use yubico_manager::keyboard::{KeyboardLayout, ScanMap};
+use yubico_manager::ndef::NdefPayload;
+use yubico_manager::otpmode::Aes128Key;
+use yubico_manager::yubicoerror::YubicoError;
+use yubico_manager::Yubico;
+
+const SERIAL: u32 = 1234567;
+const SECRET: &[u8; 20] = b"my_awesome_secret_20";
+const ACCESS_CODE: &[u8; 6] = b"secret";
+
+fn yubico() -> (Yubico<Emulator>, Config) {
+    let mut yubi = Yubico::with_transport(Emulator::new(SERIAL));
+    let device = yubi.find_yubikey().unwrap();
+    let config = Config::default_config()
+        .set_vendor_id(device.vendor_id)
+        .set_product_id(device.product_id);
+    (yubi, config)
+}
+
+fn hmac_config(button_press: bool) -> DeviceModeConfig {
+    let mut device_config = DeviceModeConfig::default();
+    device_config.challenge_response_hmac(&HmacKey::from_slice(SECRET), true, button_press);
+    device_config
+}
+
+fn configure(yubi: &mut Yubico<Emulator>, config: &Config, command: Command) {
+    yubi.write_config(config.clone().set_command(command), &mut hmac_config(false))
+        .unwrap();
+}
+
+fn challenge_hmac(yubi: &mut Yubico<Emulator>, config: &Config, slot: Slot) -> bool {
+    let challenge = b"mychallenge";
+    // An empty slot never answers.
+    let config = config
+        .clone()
+        .set_slot(slot)
+        .set_timeout(Duration::from_millis(500));
+    match yubi.challenge_response_hmac(challenge, config) {
+        Ok(hmac) => hmac.check(&HmacKey::from_slice(SECRET), challenge),
+        Err(_) => false,
+    }
+}
+
+#[test]
+fn serial_and_version() {
+    let (mut yubi, config) = yubico();
+    assert_eq!(yubi.read_serial_number(config.clone()).unwrap(), SERIAL);
+    assert_eq!(yubi.read_status(config).unwrap().version, (5, 4, 3));
+
+    let devices = yubi.list_devices().unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].serial, Some(SERIAL));
+    assert_eq!(devices[0].version, Some((5, 4, 3)));
+}
+
+#[test]
+fn select_by_serial() {
+    let (mut yubi, _) = yubico();
+    let config = Config::default_config().set_serial(SERIAL);
+    assert_eq!(yubi.read_serial_number(config).unwrap(), SERIAL);
+
+    let config = Config::default_config().set_serial(SERIAL + 1);
+    match yubi.read_serial_number(config) {
+        Err(YubicoError::DeviceNotFound) => {}
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn serial_visibility() {
+    let (mut yubi, config) = yubico();
+    configure(&mut yubi, &config, Command::Configuration1);
+    // The key no longer answers the serial query.
+    assert_eq!(yubi.list_devices().unwrap()[0].serial, None);
+
+    let mut device_config = DeviceModeConfig::default();
+    device_config
+        .yubico_otp(
+            b"public",
+            b"norway",
+            &Aes128Key::from_slice(b"_awesome_secret_"),
+            TicketFlags::APPEND_CR,
+        )
+        .unwrap();
+    yubi.write_config(
+        config.clone().set_command(Command::Configuration2),
+        &mut device_config,
+    )
+    .unwrap();
+    assert_eq!(yubi.list_devices().unwrap()[0].serial, Some(SERIAL));
+    let config = Config::default_config().set_serial(SERIAL);
+    assert_eq!(yubi.read_serial_number(config).unwrap(), SERIAL);
+}
+
+#[test]
+fn write_and_challenge_hmac() {
+    let (mut yubi, config) = yubico();
+    configure(&mut yubi, &config, Command::Configuration1);
+
+    assert!(challenge_hmac(&mut yubi, &config, Slot::Slot1));
+    let status = yubi.slot_status(config.clone(), Slot::Slot1).unwrap();
+    assert!(status.configured);
+    assert!(!status.touch_required);
+    assert!(!yubi.slot_status(config, Slot::Slot2).unwrap().configured);
+    assert_eq!(yubi.transport().pgm_seq(), 1);
+}
+
+#[test]
+fn write_and_challenge_otp() {
+    let key = Aes128Key::from_slice(b"_awesome_secret_");
+    let private_identity = b"norway";
+    let (mut yubi, config) = yubico();
+    let mut device_config = DeviceModeConfig::default();
+    device_config.challenge_response_otp(&key, private_identity, false);
+    yubi.write_config(
+        config.clone().set_command(Command::Configuration2),
+        &mut device_config,
+    )
+    .unwrap();
+
+    let challenge = b"my_challenge";
+    let block = yubi
+        .challenge_response_otp(challenge, config.set_mode(Mode::Otp).set_slot(Slot::Slot2))
+        .unwrap();
+    assert_eq!(&block.check(&key, challenge).unwrap().uid, private_identity);
+}
+
+#[test]
+fn wrong_access_code() {
+    let (mut yubi, config) = yubico();
+    let config = config.set_command(Command::Configuration1);
+    let mut device_config = hmac_config(false);
+    device_config.set_access_code(ACCESS_CODE);
+    yubi.write_config(config.clone(), &mut device_config)
+        .unwrap();
+
+    match yubi.write_config(config.clone(), &mut hmac_config(false)) {
+        Err(YubicoError::ConfigNotWritten) => {}
+        other => panic!("{:?}", other),
+    }
+    match yubi.delete_slot(config.clone(), Slot::Slot1, Some(b"wrong!")) {
+        Err(YubicoError::ConfigNotWritten) => {}
+        other => panic!("{:?}", other),
+    }
+    assert!(
+        yubi.slot_status(config.clone(), Slot::Slot1)
+            .unwrap()
+            .configured
+    );
+
+    yubi.write_config_with_access_code(config, &mut hmac_config(false), Some(ACCESS_CODE))
+        .unwrap();
+}
+
+#[test]
+fn delete_slot() {
+    let (mut yubi, config) = yubico();
+    configure(&mut yubi, &config, Command::Configuration1);
+    configure(&mut yubi, &config, Command::Configuration2);
+
+    yubi.delete_slot(config.clone(), Slot::Slot1, None).unwrap();
+    assert!(
+        !yubi
+            .slot_status(config.clone(), Slot::Slot1)
+            .unwrap()
+            .configured
+    );
+    assert!(
+        yubi.slot_status(config.clone(), Slot::Slot2)
+            .unwrap()
+            .configured
+    );
+    assert!(!challenge_hmac(&mut yubi, &config, Slot::Slot1));
+
+    // The sequence drops to 0 once no slot is configured.
+    yubi.delete_slot(config.clone(), Slot::Slot2, None).unwrap();
+    assert_eq!(yubi.transport().pgm_seq(), 0);
+    yubi.delete_slot(config, Slot::Slot2, None).unwrap();
+}
+
+#[test]
+fn swap_slots() {
+    let (mut yubi, config) = yubico();
+    configure(&mut yubi, &config, Command::Configuration1);
+
+    yubi.swap_slots(config.clone(), None).unwrap();
+    assert!(
+        !yubi
+            .slot_status(config.clone(), Slot::Slot1)
+            .unwrap()
+            .configured
+    );
+    assert!(challenge_hmac(&mut yubi, &config, Slot::Slot2));
+
+    yubi.delete_slot(config.clone(), Slot::Slot2, None).unwrap();
+    // Swapping two empty slots leaves the key blank.
+    yubi.swap_slots(config, None).unwrap();
+}
+
+#[test]
+fn update_slot() {
+    let (mut yubi, config) = yubico();
+    let mut device_config = hmac_config(false);
+    device_config.ext_flags = ExtendedFlags::ALLOW_UPDATE;
+    yubi.write_config(
+        config.clone().set_command(Command::Configuration1),
+        &mut device_config,
+    )
+    .unwrap();
+
+    // Flags outside of the update masks are dropped: the slot stays in
+    // HMAC challenge-response mode, with its secret.
+    let update = UpdateConfig::default()
+        .set_ticket_flags(TicketFlags::APPEND_CR | TicketFlags::PROTECT_CFG2)
+        .set_config_flags(ConfigFlags::HMAC_LT64 | ConfigFlags::MAN_UPDATE)
+        .set_extended_flags(ExtendedFlags::ALLOW_UPDATE | ExtendedFlags::LED_INV);
+    assert_eq!(update.ticket_flags(), TicketFlags::APPEND_CR);
+    assert_eq!(update.config_flags(), ConfigFlags::HMAC_LT64);
+    yubi.update_slot(config.clone(), Slot::Slot1, &update, None)
+        .unwrap();
+
+    assert!(challenge_hmac(&mut yubi, &config, Slot::Slot1));
+    assert!(yubi.read_status(config.clone()).unwrap().is_led_inverted());
+
+    // Without ALLOW_UPDATE, the slot can not be updated any more.
+    let update = UpdateConfig::default().set_extended_flags(ExtendedFlags::empty());
+    yubi.update_slot(config.clone(), Slot::Slot1, &update, None)
+        .unwrap();
+    match yubi.update_slot(config, Slot::Slot1, &update, None) {
+        Err(YubicoError::ConfigNotWritten) => {}
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn change_access_code() {
+    let (mut yubi, config) = yubico();
+    let mut device_config = DeviceModeConfig::default();
+    device_config
+        .yubico_otp(
+            b"public",
+            b"norway",
+            &Aes128Key::from_slice(b"_awesome_secret_"),
+            TicketFlags::APPEND_CR,
+        )
+        .unwrap();
+    device_config.ext_flags |= ExtendedFlags::ALLOW_UPDATE;
+    yubi.write_config(
+        config.clone().set_command(Command::Configuration1),
+        &mut device_config,
+    )
+    .unwrap();
+
+    let update = UpdateConfig::default();
+    yubi.change_access_code(
+        config.clone(),
+        Slot::Slot1,
+        &update,
+        None,
+        Some(ACCESS_CODE),
+    )
+    .unwrap();
+    match yubi.update_slot(config.clone(), Slot::Slot1, &update, None) {
+        Err(YubicoError::ConfigNotWritten) => {}
+        other => panic!("{:?}", other),
+    }
+
+    // The default update keeps the slot updatable, and the serial
+    // visible.
+    yubi.change_access_code(
+        config.clone(),
+        Slot::Slot1,
+        &update,
+        Some(ACCESS_CODE),
+        None,
+    )
+    .unwrap();
+    yubi.update_slot(config, Slot::Slot1, &update, None)
+        .unwrap();
+    assert_eq!(yubi.list_devices().unwrap()[0].serial, Some(SERIAL));
+}
+
+#[test]
+fn writes_on_a_blank_key() {
+    let (mut yubi, config) = yubico();
+    let uri = NdefPayload::Uri("https://example.com".to_string());
+    yubi.write_ndef(config.clone(), Slot::Slot1, &uri, None)
+        .unwrap();
+    assert_eq!(
+        yubi.transport().ndef(&Slot::Slot1),
+        Some(&uri.to_bytes().unwrap()[..14])
+    );
+
+    let scan_map = ScanMap::from_layout(KeyboardLayout::De).unwrap();
+    yubi.write_scan_map(config.clone(), &scan_map).unwrap();
+    assert_eq!(yubi.transport().scan_map(), &scan_map);
+
+    yubi.set_device_mode(config, DeviceMode::OtpU2fCcid, 10, 0)
+        .unwrap();
+    assert_eq!(yubi.transport().pgm_seq(), 0);
+}
+
+#[test]
+fn device_info() {
+    let (mut yubi, config) = yubico();
+    let info = yubi.read_device_info(config.clone()).unwrap();
+    assert_eq!(info.serial, Some(SERIAL));
+    assert_eq!(info.version, Some((5, 4, 3)));
+    assert!(info.usb_enabled.contains(Capabilities::PIV));
+
+    let update = DeviceConfigUpdate::default()
+        .set_usb_enabled(info.usb_enabled - Capabilities::PIV)
+        .set_challenge_response_timeout(30)
+        .set_auto_eject_timeout(300);
+    yubi.write_device_config(config.clone(), &update).unwrap();
+
+    let info = yubi.read_device_info(config).unwrap();
+    assert!(!info.usb_enabled.contains(Capabilities::PIV));
+    assert!(info.usb_enabled.contains(Capabilities::OTP));
+    assert_eq!(info.challenge_response_timeout, 30);
+    assert_eq!(info.auto_eject_timeout, 300);
+}
+
+#[test]
+fn device_info_needs_firmware_4_1() {
+    let mut yubi = Yubico::with_transport(Emulator::new(SERIAL).with_version(3, 4, 0));
+    let config = Config::default_config().set_product_id(0x0407);
+    match yubi.read_device_info(config) {
+        Err(YubicoError::CommandNotSupported) => {}
+        other => panic!("{:?}", other),
+    }
+}
+
+fn touch_events(emulator: Emulator, challenge: impl FnOnce(Config) -> Config) -> Vec<TouchEvent> {
+    let mut yubi = Yubico::with_transport(emulator);
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    let config = Config::default_config()
+        .set_product_id(0x0407)
+        .set_touch_callback(move |event| recorded.lock().unwrap().push(event));
+    yubi.write_config(
+        config.clone().set_command(Command::Configuration1),
+        &mut hmac_config(true),
+    )
+    .unwrap();
+
+    let _ = yubi.challenge_response_hmac(b"mychallenge", challenge(config));
+    let events = events.lock().unwrap().clone();
+    events
+}
+
+#[test]
+fn touch() {
+    let events = touch_events(Emulator::new(SERIAL), |config| config);
+    assert_eq!(events, [TouchEvent::Waiting, TouchEvent::Touched]);
+
+    let events = touch_events(Emulator::new(SERIAL).with_touch(false), |config| config);
+    assert_eq!(events, [TouchEvent::Waiting, TouchEvent::TimedOut]);
+
+    let events = touch_events(Emulator::new(SERIAL).with_touch(false), |config| {
+        config.set_timeout(Duration::from_millis(0))
+    });
+    assert_eq!(events, [TouchEvent::Waiting, TouchEvent::TimedOut]);
+
+    let cancellation = CancellationToken::new();
+    cancellation.cancel();
+    let events = touch_events(Emulator::new(SERIAL), |config| {
+        config.set_cancellation_token(cancellation)
+    });
+    assert_eq!(events, [TouchEvent::Waiting, TouchEvent::Cancelled]);
+}